@@ -1,6 +1,6 @@
 //! login_required macros for actix-loginmanager
 //! # Example
-//! ```rust
+//! ```ignore
 //! use actix_loginmanager::login_required;
 //! // define or import `user` which implements `UserMinix` trait.
 //! 
@@ -15,6 +15,12 @@
 //!     user.is_actived(); //can access user:Rc<User>
 //!     return "hello";
 //! }
+//! 
+//! #[login_required(User, sync)]
+//! async fn hello()->impl actix_web::Responder{
+//!     user.is_actived(); //can access user:Arc<User>
+//!     return "hello";
+//! }
 //! ```
 
 use proc_macro::TokenStream;
@@ -23,15 +29,16 @@ use proc_macro::TokenStream;
 /// 
 /// # Syntax
 /// ```text
-/// #[login_required(UserType,name="user")]
+/// #[login_required(UserType,name="user",sync)]
 /// ```
 /// 
 /// # Attributes
 /// - `UserType` - Define the variable type.
 /// - `name="user"` - Define the variable name.
+/// - `sync` - Inject `SyncUserWrapAuth(SyncUserWrap(user))` instead, the user is an `Arc<User>`.
 /// 
 /// # Example
-/// ```rust
+/// ```ignore
 /// #[login_required(User)]
 /// async fn hello()->impl actix_web::Responder{
 ///     user.is_actived(); //can access user:Rc<User>
@@ -45,6 +52,7 @@ pub fn login_required(args: TokenStream, item: TokenStream) -> TokenStream {
     let args = syn::parse_macro_input!(args as syn::AttributeArgs);
     let mut user = None;
     let mut name = "user".to_owned();
+    let mut sync = false;
     for arg in args{
         match arg{
             NestedMeta::Lit(Lit::Str(lit))=> match user{
@@ -55,6 +63,9 @@ pub fn login_required(args: TokenStream, item: TokenStream) -> TokenStream {
                         .into();
                 }
             },
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("sync")=>{
+                sync = true;
+            },
             NestedMeta::Meta(Meta::Path(path))=>match user{
                 None=>{
                     user = Some(path.segments.first().unwrap().ident.clone().to_string());
@@ -88,7 +99,11 @@ pub fn login_required(args: TokenStream, item: TokenStream) -> TokenStream {
     let vis = &input.vis;
     let sig = &mut input.sig;
     let body = &input.block;
-    let param = format!("actix_loginmanager::UserWrapAuth(actix_loginmanager::UserWrap({})): actix_loginmanager::UserWrapAuth<{}>",name,user.unwrap());
+    let param = if sync{
+        format!("actix_loginmanager::SyncUserWrapAuth(actix_loginmanager::SyncUserWrap({})): actix_loginmanager::SyncUserWrapAuth<{}>",name,user.unwrap())
+    }else{
+        format!("actix_loginmanager::UserWrapAuth(actix_loginmanager::UserWrap({})): actix_loginmanager::UserWrapAuth<{}>",name,user.unwrap())
+    };
     let param =  syn::parse_str(&param).unwrap();
     sig.inputs.push(param);
 
@@ -0,0 +1,136 @@
+use crate::user::UserMinix;
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The user shared by the user cache across the workers.
+pub type SharedUser = Arc<dyn Any + Send + Sync>;
+
+type CacheKey = (TypeId, String);
+
+struct Entry {
+    user: SharedUser,
+    expires: Instant,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<CacheKey, Entry>,
+    /// The keys ordered by the last use, the least recently used first.
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl Inner {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.tick);
+        }
+    }
+}
+
+/// The cache of the users loaded by `UserMinix::get_user`, keyed by the serialized user key.
+///
+/// The entries expire after `ttl`, and the least recently used one is dropped when
+/// `max_size` is exceeded. It is shared by cloning, create it outside of the app
+/// factory so that all the workers use the same cache. Only the users returning
+/// `Some` from `UserMinix::to_shared` are cached.
+/// ```ignore
+/// let cache = UserCache::new(Duration::from_secs(60), 10_000);
+/// HttpServer::new(move || {
+///     App::new().wrap(LoginManager::new(CookieSession::new(&[0; 32])).user_cache(cache.clone()))
+/// })
+/// ```
+#[derive(Clone)]
+pub struct UserCache {
+    ttl: Duration,
+    max_size: usize,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl UserCache {
+    pub fn new(ttl: Duration, max_size: usize) -> Self {
+        Self {
+            ttl,
+            max_size,
+            inner: Arc::new(Mutex::new(Inner::default())),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn get<U: 'static>(&self, key_str: &str) -> Option<SharedUser> {
+        let key = (TypeId::of::<U>(), key_str.to_owned());
+        let mut inner = self.lock();
+        let (expires, old_tick) = inner
+            .entries
+            .get(&key)
+            .map(|entry| (entry.expires, entry.tick))?;
+        if expires <= Instant::now() {
+            inner.remove(&key);
+            return None;
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.order.remove(&old_tick);
+        inner.order.insert(tick, key.clone());
+        let entry = inner.entries.get_mut(&key)?;
+        entry.tick = tick;
+        Some(entry.user.clone())
+    }
+
+    pub(crate) fn insert<U: 'static>(&self, key_str: &str, user: SharedUser) {
+        if self.max_size == 0 {
+            return;
+        }
+        let key = (TypeId::of::<U>(), key_str.to_owned());
+        let mut inner = self.lock();
+        inner.remove(&key);
+        while inner.entries.len() >= self.max_size {
+            let oldest = match inner.order.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            inner.remove(&oldest);
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.order.insert(tick, key.clone());
+        inner.entries.insert(
+            key,
+            Entry {
+                user,
+                expires: Instant::now() + self.ttl,
+                tick,
+            },
+        );
+    }
+
+    /// Drop the cached user, call it after the user is changed or deleted.
+    pub fn invalidate_user<U>(&self, key: &U::Key)
+    where
+        U: UserMinix + 'static,
+    {
+        if let Ok(key_str) = serde_json::to_string(key) {
+            self.lock().remove(&(TypeId::of::<U>(), key_str));
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.entries.clear();
+        inner.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
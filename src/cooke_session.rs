@@ -1,7 +1,6 @@
 use actix_web::http::{header::{HeaderValue, self, SET_COOKIE}};
-use actix_web::HttpMessage;
 use actix_web::{
-    dev::{ServiceRequest, ServiceResponse},
+    dev::{ResponseHead, ServiceRequest},
     Error, HttpRequest,
 };
 
@@ -14,7 +13,8 @@ use crypto::sha2::Sha512;
 
 use crypto::digest::Digest;
 
-use crate::loginmanager::{DecodeRequest, LoginInfo, LoginState};
+use crate::error::LoginManagerError;
+use crate::loginmanager::{new_session_id, DecodeRequest, LoginInfo, LoginState};
 
 /// use cookie as session to storage the info of user key.
 pub struct CookieSession {
@@ -29,40 +29,52 @@ pub struct CookieSession {
     same_site: Option<SameSite>,
 }
 
-fn __create_identifier(request: &ServiceRequest) -> String {
+/// The fingerprint of the client ip and user agent.
+pub(crate) fn fingerprint(ip: Option<&str>, agent: Option<&str>) -> String {
     let mut sha512 = Sha512::new();
-    if let Some(addr) = request.connection_info().realip_remote_addr() {
-        if let Some(ip) = addr.split(":").next() {
-            sha512.input_str(ip);
-        };
+    if let Some(ip) = ip {
+        sha512.input_str(ip);
     }
-    if let Some(agent) = request.headers().get(header::USER_AGENT) {
-        if let Ok(agent) = agent.to_str() {
-            sha512.input_str(agent);
-        };
-    };
-    return sha512.result_str();
+    if let Some(agent) = agent {
+        sha512.input_str(agent);
+    }
+    sha512.result_str()
 }
 
 fn _create_identifier(request: &HttpRequest) -> String {
-    let mut sha512 = Sha512::new();
-    if let Some(addr) = request.connection_info().realip_remote_addr() {
-        if let Some(ip) = addr.split(":").next() {
-            sha512.input_str(ip);
-        };
-    }
-    if let Some(agent) = request.headers().get(header::USER_AGENT) {
-        if let Ok(agent) = agent.to_str() {
-            sha512.input_str(agent);
-        };
-    };
-    return sha512.result_str();
+    let connection_info = request.connection_info();
+    let ip = connection_info
+        .realip_remote_addr()
+        .and_then(|addr| addr.split(':').next());
+    let agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|agent| agent.to_str().ok());
+    fingerprint(ip, agent)
+}
+
+fn set_cookie(res: &mut ResponseHead, cookie: &Cookie) -> Result<(), Error> {
+    let val = HeaderValue::from_str(&cookie.encoded().to_string())
+        .map_err(|err| LoginManagerError::Session(err.to_string()))?;
+    res.headers_mut().append(SET_COOKIE, val);
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize)]
-struct Session {
-    id: String,
-    user_id: Option<String>,
+pub(crate) struct Session {
+    /// The random session id.
+    pub(crate) id: String,
+    /// The fingerprint of the client, the sessions before it have the fingerprint as the id.
+    #[serde(default)]
+    pub(crate) fingerprint: String,
+    /// The key of the active account.
+    pub(crate) user_id: Option<String>,
+    #[serde(default)]
+    pub(crate) accounts: Vec<String>,
+    #[serde(default)]
+    pub(crate) impersonators: Vec<String>,
+    #[serde(default)]
+    pub(crate) pending: bool,
 }
 
 impl CookieSession {
@@ -116,47 +128,26 @@ impl CookieSession {
     }
 }
 
-impl DecodeRequest for CookieSession {
-    fn decode(&self, req: &ServiceRequest) -> Option<String> {
-        if let Some(cookie) = req.cookie(&self.name) {
-            let mut jar = CookieJar::new();
-            jar.add_original(cookie.clone());
-            let cookie_opt = jar.private(&self.key).get(&self.name);
-            if let Some(cookie) = cookie_opt {
-                if let Ok(val) = serde_json::from_str::<Session>(cookie.value()) {
-                    if val.id == __create_identifier(&req) {
-                        return val.user_id;
-                    };
-                }
-            }
-        };
-        None
+impl CookieSession {
+    pub(crate) fn cookie_name(&self) -> &str {
+        &self.name
     }
 
-    fn update_<B>(&self, res: &mut ServiceResponse<B>) -> Result<(), Error> {
-        let key = match res.request().extensions().get::<LoginInfo>() {
-            Some(LoginInfo {
-                key_str,
-                state: LoginState::Login | LoginState::Update,
-            }) => key_str.clone(),
-            Some(LoginInfo {
-                state: LoginState::Logout,
-                ..
-            }) => Some("".to_owned()),
-            _ => None,
-        };
-        let key = match key {
-            Some(x) if x == "".to_owned() => None,
-            Some(key) => Some(key),
-            _ => return Ok(()),
-        };
-
-        let session = Session {
-            id: _create_identifier(res.request()),
-            user_id: key,
-        };
+    /// Decrypt the session cookie, without checking the fingerprint.
+    pub(crate) fn read_cookie(&self, cookie: &Cookie) -> Option<Session> {
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie.clone().into_owned());
+        let cookie = jar.private(&self.key).get(&self.name)?;
+        serde_json::from_str::<Session>(cookie.value()).ok()
+    }
 
-        let value = serde_json::to_string(&session).map_err(|_| "").unwrap();
+    /// Encrypt the session into the cookie.
+    pub(crate) fn session_cookie(
+        &self,
+        session: &Session,
+    ) -> Result<Cookie<'static>, LoginManagerError> {
+        let value = serde_json::to_string(session)
+            .map_err(|err| LoginManagerError::Session(err.to_string()))?;
 
         let mut cookie = Cookie::new(self.name.clone(), value);
 
@@ -182,14 +173,94 @@ impl DecodeRequest for CookieSession {
 
         let mut jar = CookieJar::new();
         jar.private_mut(&self.key).add(cookie);
+        jar.delta()
+            .next()
+            .cloned()
+            .ok_or_else(|| LoginManagerError::Session("the cookie is not encrypted".to_owned()))
+    }
 
-        for cookie in jar.delta() {
-            let val = HeaderValue::from_str(&cookie.encoded().to_string())
-                .map_err(|_| ())
-                .unwrap();
-            res.headers_mut().append(SET_COOKIE, val);
+    /// The expired and empty session cookie, of the same path and domain.
+    fn removal_cookie(&self) -> Cookie<'static> {
+        let mut cookie = Cookie::new(self.name.clone(), "");
+        cookie.set_path(self.path.clone());
+        cookie.set_secure(self.secure);
+        cookie.set_http_only(self.http_only);
+        if let Some(ref domain) = self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        if let Some(same_site) = self.same_site {
+            cookie.set_same_site(same_site);
+        }
+        cookie.make_removal();
+        cookie
+    }
+
+    fn session(&self, req: &ServiceRequest) -> Option<Session> {
+        let session = self.read_cookie(&req.cookie(&self.name)?)?;
+        let fingerprint = match session.fingerprint.as_str() {
+            "" => &session.id,
+            fingerprint => fingerprint,
+        };
+        if *fingerprint == _create_identifier(req.request()) {
+            Some(session)
+        } else {
+            None
         }
+    }
+}
+
+impl DecodeRequest for CookieSession {
+    fn decode(&self, req: &ServiceRequest) -> Option<String> {
+        self.session(req).and_then(|session| session.user_id)
+    }
+
+    fn decode_info(&self, req: &ServiceRequest) -> LoginInfo {
+        let session = match self.session(req) {
+            Some(session) => session,
+            None => return LoginInfo::new(None, LoginState::Wait),
+        };
+        let mut info = LoginInfo::new(session.user_id, LoginState::Wait);
+        // The sessions before multiple accounts only have the active one.
+        if !session.accounts.is_empty() {
+            info.accounts = session.accounts;
+        }
+        info.impersonators = session.impersonators;
+        info.pending = session.pending;
+        info.session_id = Some(session.id);
+        info
+    }
+
+    fn update_(
+        &self,
+        info: Option<&LoginInfo>,
+        req: &HttpRequest,
+        res: &mut ResponseHead,
+    ) -> Result<(), Error> {
+        let session = match info {
+            Some(LoginInfo {
+                key_str: Some(key_str),
+                state: LoginState::Login | LoginState::Partial | LoginState::Update,
+                accounts,
+                impersonators,
+                pending,
+                session_id,
+                ..
+            }) => Session {
+                id: session_id.clone().unwrap_or_else(new_session_id),
+                fingerprint: _create_identifier(req),
+                user_id: Some(key_str.clone()),
+                accounts: accounts.clone(),
+                impersonators: impersonators.clone(),
+                pending: *pending,
+            },
+            Some(LoginInfo {
+                state: LoginState::Logout,
+                ..
+            }) => return set_cookie(res, &self.removal_cookie()),
+            _ => return Ok(()),
+        };
 
-        Ok(())
+        let cookie = self.session_cookie(&session)?;
+        set_cookie(res, &cookie)
     }
 }
@@ -0,0 +1,87 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt;
+
+/// The errors of the configuration and the session of LoginManager.
+#[derive(Debug)]
+pub enum LoginManagerError {
+    /// The url is not visible ASCII, the other characters must be percent-encoded.
+    InvalidUrl(String),
+    /// The cookie or the header to send is not a valid header value.
+    InvalidHeader(String),
+    /// The login or mfa view is a relative url, which redirects to itself again and again.
+    RelativeView(String),
+    /// The session can not be written to the response.
+    Session(String),
+    /// The user extractors are used without the LoginManager of the realm.
+    MissingMiddleware(String),
+    /// `max_sessions_per_user` is set without `session_registry`.
+    MissingRegistry,
+}
+
+impl fmt::Display for LoginManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginManagerError::InvalidUrl(url) => write!(f, "invalid url {:?}", url),
+            LoginManagerError::InvalidHeader(value) => write!(f, "invalid header {:?}", value),
+            LoginManagerError::RelativeView(url) => {
+                write!(f, "the view {:?} must be a path or an absolute url", url)
+            }
+            LoginManagerError::Session(err) => write!(f, "failed to write the session: {}", err),
+            LoginManagerError::MissingMiddleware(realm) => write!(
+                f,
+                "no LoginManager of the realm {:?} wraps the service",
+                realm
+            ),
+            LoginManagerError::MissingRegistry => {
+                f.write_str("max_sessions_per_user needs a session_registry")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoginManagerError {}
+
+/// The errors are `500 Internal Server Error`, the detail is only logged.
+impl ResponseError for LoginManagerError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::new(self.status_code())
+    }
+}
+
+/// The error of `AsyncDecodeRequest::decode`, the invalid credentials are anonymous instead.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The store or the remote service is not available, `503 Service Unavailable`.
+    Unavailable(String),
+    /// `500 Internal Server Error`.
+    Internal(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Unavailable(err) => write!(f, "the decoder is unavailable: {}", err),
+            DecodeError::Internal(err) => write!(f, "failed to decode: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl ResponseError for DecodeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DecodeError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            DecodeError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::new(self.status_code())
+    }
+}
@@ -0,0 +1,253 @@
+use crate::next::safe_next;
+use crate::unauthorized::{accepts_json, is_xhr};
+use crate::user::{UserMinix, UserWrap};
+use actix_web::dev::{AppService, HttpServiceFactory};
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Route};
+use futures::Future;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// The reason the credentials are rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthError {
+    /// No such user or the wrong password.
+    InvalidCredentials,
+    /// The user is not actived, like locked or not confirmed.
+    Inactive,
+    /// The user has `max_sessions_per_user` sessions with `SessionLimit::Reject`.
+    TooManySessions,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => f.write_str("The credentials are invalid."),
+            AuthError::Inactive => f.write_str("The user is not active."),
+            AuthError::TooManySessions => f.write_str("The user has too many sessions."),
+        }
+    }
+}
+
+/// Check the credentials of the login form or JSON body, for `LoginHandler`.
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Credentials {
+///     name: String,
+///     password: String,
+/// }
+///
+/// struct Auth(SqlitePool);
+///
+/// impl Authenticator for Auth {
+///     type User = User;
+///     type Credentials = Credentials;
+///     type Future = Pin<Box<dyn Future<Output = Result<User, AuthError>>>>;
+///
+///     fn authenticate(&self, credentials: Credentials, _: &HttpRequest) -> Self::Future {
+///         let pool = self.0.clone();
+///         Box::pin(async move {
+///             let user = User::find_by_name(&pool, &credentials.name).await;
+///             match Passwords::new().verify_user(&credentials.password, user.as_ref().map(|u| u.hash.as_str())) {
+///                 Verified::Invalid => Err(AuthError::InvalidCredentials),
+///                 _ => user.ok_or(AuthError::InvalidCredentials),
+///             }
+///         })
+///     }
+/// }
+/// ```
+pub trait Authenticator: 'static {
+    type User: UserMinix + 'static;
+    type Credentials: DeserializeOwned + 'static;
+    type Future: Future<Output = Result<Self::User, AuthError>>;
+
+    fn authenticate(&self, credentials: Self::Credentials, req: &HttpRequest) -> Self::Future;
+}
+
+/// Whether to answer the request with JSON instead of the redirect.
+fn wants_json(req: &HttpRequest) -> bool {
+    is_xhr(req) || accepts_json(req) || req.content_type() == "application/json"
+}
+
+/// The response to the `next` url of the request.
+fn to_next(req: &HttpRequest) -> HttpResponse {
+    let next = safe_next(req);
+    if wants_json(req) {
+        HttpResponse::Ok().json(json!({ "next": next }))
+    } else {
+        HttpResponse::SeeOther()
+            .insert_header((LOCATION, next))
+            .finish()
+    }
+}
+
+struct LoginConfig<A> {
+    authenticator: A,
+    partial: bool,
+}
+
+async fn login<A: Authenticator>(
+    req: HttpRequest,
+    config: web::Data<LoginConfig<A>>,
+    credentials: web::Either<web::Json<A::Credentials>, web::Form<A::Credentials>>,
+) -> HttpResponse {
+    let credentials = match credentials {
+        web::Either::Left(json) => json.into_inner(),
+        web::Either::Right(form) => form.into_inner(),
+    };
+    let result = config
+        .authenticator
+        .authenticate(credentials, &req)
+        .await
+        .and_then(|user| {
+            let user = UserWrap::from(user);
+            let login = if config.partial {
+                crate::try_login_partial(&user, &req)
+            } else {
+                crate::try_login(&user, &req)
+            };
+            login.map_err(|_| AuthError::TooManySessions)
+        });
+    match result {
+        Ok(_) => to_next(&req),
+        // Not 401, which LoginManager redirects to the login view unless it is this path.
+        Err(err) if wants_json(&req) => HttpResponse::Forbidden().json(json!({
+            "error": err,
+            "detail": err.to_string(),
+        })),
+        // Back to the login view with the error, the `next` is kept.
+        Err(err) => {
+            let error = serde_json::to_value(err).unwrap_or_default();
+            let mut location = format!(
+                "{}?error={}",
+                req.path(),
+                error.as_str().unwrap_or_default()
+            );
+            let query = web::Query::<Vec<(String, String)>>::from_query(req.query_string())
+                .map(web::Query::into_inner)
+                .unwrap_or_default();
+            for (name, value) in query.iter().filter(|(name, _)| name != "error") {
+                location.push_str(&format!(
+                    "&{}={}",
+                    urlencoding::encode(name),
+                    urlencoding::encode(value)
+                ));
+            }
+            HttpResponse::SeeOther()
+                .insert_header((LOCATION, location))
+                .finish()
+        }
+    }
+}
+
+/// The POST service logs in by the `Authenticator`, with the form or JSON body.
+///
+/// It redirects to the safe `next` url that LoginManager appends to the login view,
+/// or answers `{"next": url}` to the JSON and XHR requests. The rejected credentials
+/// redirect back with the `error` query, or `403 Forbidden` with JSON.
+/// ```ignore
+/// App::new()
+///     .wrap(LoginManager::new(CookieSession::new(&[0; 32])))
+///     .service(LoginHandler::new(Auth(pool.clone())).route(web::get().to(login_page)))
+///     .service(LogoutHandler::<User>::new())
+/// ```
+pub struct LoginHandler<A> {
+    path: String,
+    config: LoginConfig<A>,
+    routes: Vec<Route>,
+}
+
+impl<A: Authenticator> LoginHandler<A> {
+    pub fn new(authenticator: A) -> Self {
+        Self {
+            path: "/login".to_owned(),
+            config: LoginConfig {
+                authenticator,
+                partial: false,
+            },
+            routes: Vec::new(),
+        }
+    }
+
+    /// Set the path, default '/login'.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+        self
+    }
+
+    /// Login by `login_partial`, for the second factor after the credentials.
+    pub fn partial(mut self, partial: bool) -> Self {
+        self.config.partial = partial;
+        self
+    }
+
+    /// Add the route of the other methods to the path, like the GET login view.
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+}
+
+impl<A: Authenticator> HttpServiceFactory for LoginHandler<A> {
+    fn register(self, config: &mut AppService) {
+        let mut resource = web::resource(self.path)
+            .app_data(web::Data::new(self.config))
+            .route(web::post().to(login::<A>));
+        for route in self.routes {
+            resource = resource.route(route);
+        }
+        resource.register(config)
+    }
+}
+
+async fn logout<U: UserMinix + 'static>(
+    req: HttpRequest,
+    user: Option<UserWrap<U>>,
+) -> HttpResponse {
+    if let Some(user) = user {
+        crate::logout(&user, &req);
+    }
+    to_next(&req)
+}
+
+/// The POST service logs out all the accounts of the realm of `U`, then redirects
+/// to the safe `next` url like `LoginHandler`.
+///
+/// It only accepts POST, so that the links and images of other sites can not log out the user.
+pub struct LogoutHandler<U> {
+    path: String,
+    _user: PhantomData<U>,
+}
+
+impl<U: UserMinix + 'static> LogoutHandler<U> {
+    pub fn new() -> Self {
+        Self {
+            path: "/logout".to_owned(),
+            _user: PhantomData,
+        }
+    }
+
+    /// Set the path, default '/logout'.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+        self
+    }
+}
+
+impl<U: UserMinix + 'static> Default for LogoutHandler<U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<U: UserMinix + 'static> HttpServiceFactory for LogoutHandler<U> {
+    fn register(self, config: &mut AppService) {
+        web::resource(self.path)
+            .route(web::post().to(logout::<U>))
+            .register(config)
+    }
+}
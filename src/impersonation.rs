@@ -0,0 +1,165 @@
+use crate::loginmanager::LoginInfos;
+use crate::user::{UserMinix, UserWrap};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::ErrorForbidden;
+use actix_web::{Error, FromRequest, HttpRequest};
+use futures::{
+    future::{ok, Ready},
+    Future,
+};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::rc::Rc;
+
+/// The event of the impersonation audit hook, see `LoginManager::on_impersonation`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Impersonation {
+    Start,
+    Stop,
+}
+
+pub(crate) type ImpersonationHook = Rc<dyn Fn(&HttpRequest, Impersonation, &str, &str)>;
+
+/// Call the audit hook of the realm with the keys of the impersonator and the user.
+pub(crate) fn audit(
+    req: &HttpRequest,
+    realm: &str,
+    event: Impersonation,
+    impersonator: &str,
+    user: &str,
+) {
+    let hook =
+        LoginInfos::config(req, Some(realm)).and_then(|config| config.on_impersonation.clone());
+    if let Some(hook) = hook {
+        hook(req, event, impersonator, user);
+    }
+}
+
+/// The real user impersonating the logged in user of the realm of `U`,
+/// `None` if the request is not impersonated. It implements `FromRequest` trait and never fails.
+/// ```ignore
+/// #[get("/")]
+/// async fn index(UserWrap(user): UserWrap<User>, impersonator: Impersonator<User>) -> impl Responder {
+///     match impersonator.user() {
+///         Some(admin) => format!("{} as {}", admin.name, user.name),
+///         None => user.name.clone(),
+///     }
+/// }
+/// ```
+pub struct Impersonator<U>(pub Option<UserWrap<U>>);
+
+impl<U> Impersonator<U> {
+    pub fn user(&self) -> Option<&U> {
+        self.0.as_ref().map(|user| user.0.as_ref())
+    }
+
+    pub fn is_impersonated(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+impl<U: 'static> FromRequest for Impersonator<U>
+where
+    U: UserMinix,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let key_str =
+                LoginInfos::get(&req, U::REALM, |info| info.impersonators.first().cloned())
+                    .flatten();
+            let key =
+                match key_str.and_then(|key_str| serde_json::from_str::<U::Key>(&key_str).ok()) {
+                    Some(key) => key,
+                    None => return Ok(Impersonator(None)),
+                };
+            let user = U::get_user(&key, &req).await;
+            Ok(Impersonator(user.map(UserWrap::from)))
+        })
+    }
+}
+
+/// The middleware rejects the impersonated requests in the realm of `U`
+/// with `403 Forbidden`, for the sensitive services like changing the password.
+/// ```ignore
+/// App::new()
+///     .wrap(LoginManager::new(CookieSession::new(&[0; 32])))
+///     .service(
+///         web::scope("/account")
+///             .wrap(BlockImpersonation::<User>::new())
+///             .service(change_password),
+///     )
+/// ```
+pub struct BlockImpersonation<U> {
+    _user: PhantomData<U>,
+}
+
+impl<U> BlockImpersonation<U> {
+    pub fn new() -> Self {
+        Self { _user: PhantomData }
+    }
+}
+
+impl<U> Default for BlockImpersonation<U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, B, U: 'static> Transform<S, ServiceRequest> for BlockImpersonation<U>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    U: UserMinix,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = BlockImpersonationMiddleware<S, U>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(BlockImpersonationMiddleware {
+            service,
+            _user: PhantomData,
+        })
+    }
+}
+
+pub struct BlockImpersonationMiddleware<S, U> {
+    service: S,
+    _user: PhantomData<U>,
+}
+
+impl<S, B, U: 'static> Service<ServiceRequest> for BlockImpersonationMiddleware<S, U>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    U: UserMinix,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + 'static>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let impersonated = LoginInfos::get(req.request(), U::REALM, |info| {
+            !info.impersonators.is_empty()
+        })
+        .unwrap_or_default();
+        if impersonated {
+            let err = ErrorForbidden("not allowed while impersonating");
+            return Box::pin(async move { Ok(req.error_response(err).map_into_right_body()) });
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move { Ok(fut.await?.map_into_left_body()) })
+    }
+}
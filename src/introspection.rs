@@ -0,0 +1,288 @@
+//! The decoder of the opaque bearer tokens by OAuth2 token introspection (RFC 7662),
+//! enabled by the `introspection` feature.
+//! ```ignore
+//! let introspection = Introspection::new("https://auth.example.com/introspect", "api", "secret");
+//! HttpServer::new(move || {
+//!     App::new()
+//!         .wrap(LoginManager::new(introspection.clone()).redirect(false))
+//!         .service(orders)
+//! })
+//!
+//! #[get("/orders")]
+//! async fn orders(UserWrap(user): UserWrap<User>, token: TokenInfo) -> Result<String, Error> {
+//!     token.require_scope("orders:read")?;
+//!     ...
+//! }
+//! ```
+use crate::error::DecodeError;
+use crate::loginmanager::{AsyncDecodeRequest, Principal};
+use actix_web::dev::{Payload, ServiceRequest};
+use actix_web::error::{ErrorForbidden, ErrorUnauthorized};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The introspection response of an active token. It implements `FromRequest` trait
+/// for the scope checks, which fails with `401 Unauthorized` without the bearer token.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TokenInfo {
+    #[serde(default)]
+    active: bool,
+    pub sub: Option<String>,
+    #[serde(default)]
+    pub scope: String,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    /// The expiration in unix seconds.
+    pub exp: Option<u64>,
+}
+
+impl TokenInfo {
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.split_whitespace()
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes().any(|s| s == scope)
+    }
+
+    /// `403 Forbidden` if the token has not the scope.
+    pub fn require_scope(&self, scope: &str) -> Result<(), Error> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(ErrorForbidden(format!("the scope {} is required", scope)))
+        }
+    }
+}
+
+impl FromRequest for TokenInfo {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let info = req.extensions().get::<TokenInfo>().cloned();
+        ready(info.ok_or_else(|| ErrorUnauthorized("no active bearer token")))
+    }
+}
+
+type MapKey = Box<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+struct Entry {
+    info: TokenInfo,
+    expires: SystemTime,
+    tick: u64,
+}
+
+/// The active tokens by the hash of the token, the tokens themselves are never kept.
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<String, Entry>,
+    /// The hashes ordered by the last use, the least recently used first.
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Cache {
+    fn remove(&mut self, hash: &str) {
+        if let Some(entry) = self.entries.remove(hash) {
+            self.order.remove(&entry.tick);
+        }
+    }
+
+    /// Move the entry to the most recently used.
+    fn touch(&mut self, hash: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(hash) {
+            self.order.remove(&entry.tick);
+            self.order.insert(tick, hash.to_owned());
+            entry.tick = tick;
+        }
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.input_str(token);
+    sha256.result_str()
+}
+
+struct Inner {
+    endpoint: String,
+    client_id: String,
+    client_secret: String,
+    map_key: MapKey,
+    max_ttl: Duration,
+    max_size: usize,
+    cache: Mutex<Cache>,
+}
+
+/// The decoder posts the bearer token to the introspection endpoint with the client
+/// credentials, and maps the `sub` of the active token to the user key.
+///
+/// The active tokens are cached until `exp`, the inactive ones are never cached.
+/// The least recently used one is dropped when `max_size` is exceeded.
+/// The clones share the cache, like `UserCache`.
+#[derive(Clone)]
+pub struct Introspection(Arc<Inner>);
+
+impl Introspection {
+    pub fn new(endpoint: &str, client_id: &str, client_secret: &str) -> Self {
+        Self(Arc::new(Inner {
+            endpoint: endpoint.to_owned(),
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            map_key: Box::new(|sub| serde_json::to_string(sub).ok()),
+            max_ttl: Duration::from_secs(300),
+            max_size: 10_000,
+            cache: Mutex::default(),
+        }))
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner {
+        Arc::get_mut(&mut self.0).expect("configure Introspection before cloning it")
+    }
+
+    /// Map the `sub` to the serialized user key, default the JSON string of it
+    /// for `UserMinix::Key = String`.
+    /// ```ignore
+    /// // the numeric user key
+    /// Introspection::new(endpoint, id, secret).map_key(|sub| Some(sub.to_owned()))
+    /// ```
+    pub fn map_key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.inner_mut().map_key = Box::new(f);
+        self
+    }
+
+    /// The longest time to cache a token, for the tokens without `exp`
+    /// or revoked before it. Default 5 minutes.
+    pub fn max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.inner_mut().max_ttl = max_ttl;
+        self
+    }
+
+    /// The most tokens to cache, default 10000. Zero disables the cache.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.inner_mut().max_size = max_size;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.0.cache.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn cached(&self, hash: &str) -> Option<TokenInfo> {
+        let mut cache = self.lock();
+        let expires = cache.entries.get(hash)?.expires;
+        if expires <= SystemTime::now() {
+            cache.remove(hash);
+            return None;
+        }
+        cache.touch(hash);
+        cache.entries.get(hash).map(|entry| entry.info.clone())
+    }
+
+    fn cache(&self, hash: &str, info: &TokenInfo) {
+        let now = SystemTime::now();
+        let mut expires = now + self.0.max_ttl;
+        if let Some(exp) = info.exp {
+            expires = expires.min(UNIX_EPOCH + Duration::from_secs(exp));
+        }
+        if self.0.max_size == 0 || expires <= now {
+            return;
+        }
+        let mut cache = self.lock();
+        cache.remove(hash);
+        while cache.entries.len() >= self.0.max_size {
+            let oldest = match cache.order.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            cache.remove(&oldest);
+        }
+        cache.entries.insert(
+            hash.to_owned(),
+            Entry {
+                info: info.clone(),
+                expires,
+                tick: 0,
+            },
+        );
+        cache.touch(hash);
+    }
+
+    /// Introspect the token, `None` if it is not active.
+    async fn introspect(&self, token: &str) -> Result<Option<TokenInfo>, DecodeError> {
+        let hash = token_hash(token);
+        if let Some(info) = self.cached(&hash) {
+            return Ok(Some(info));
+        }
+        let unavailable = |err: &dyn std::fmt::Display| DecodeError::Unavailable(err.to_string());
+        let form = [("token", token), ("token_type_hint", "access_token")];
+        let mut response = awc::Client::default()
+            .post(&self.0.endpoint)
+            .basic_auth(&self.0.client_id, &self.0.client_secret)
+            .send_form(&form)
+            .await
+            .map_err(|err| unavailable(&err))?;
+        if !response.status().is_success() {
+            return Err(unavailable(&response.status()));
+        }
+        let info = response
+            .json::<TokenInfo>()
+            .await
+            .map_err(|err| DecodeError::Internal(err.to_string()))?;
+        if !info.active {
+            return Ok(None);
+        }
+        self.cache(&hash, &info);
+        Ok(Some(info))
+    }
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() {
+        Some(token.trim().to_owned())
+    } else {
+        None
+    }
+}
+
+/// The unreachable endpoint fails the requests with `503 Service Unavailable`.
+impl AsyncDecodeRequest for Introspection {
+    fn decode(
+        &self,
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Option<Principal>, DecodeError>> {
+        let this = self.clone();
+        let token = bearer_token(req);
+        let req = req.request().clone();
+        Box::pin(async move {
+            let info = match token {
+                Some(token) => this.introspect(&token).await?,
+                None => None,
+            };
+            let key_str = info
+                .as_ref()
+                .and_then(|info| info.sub.as_deref())
+                .and_then(|sub| (this.0.map_key)(sub));
+            if let Some(info) = info {
+                req.extensions_mut().insert(info);
+            }
+            Ok(key_str.map(Principal::new))
+        })
+    }
+}
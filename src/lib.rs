@@ -1,7 +1,7 @@
 //! A loginmanager for actix-web
 //!
 //! ## Example
-//! ```rust
+//! ```no_run
 //! use std::pin::Pin;
 //! use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer};
 //! use actix_loginmanager as loginmanager;
@@ -71,7 +71,6 @@
 //! }
 //! 
 //! #[actix_web::main]
-//! #[test]
 //! async fn main() {
 //!     HttpServer::new(|| {
 //!         App::new()
@@ -90,34 +89,433 @@
 //!     .unwrap();
 //! }
 //! ```
+//!
+//! ## Features
+//! - `cookie-session` (default): `CookieSession`.
+//! - `test-utils`: the `test` module for the tests of the apps.
+//! - `totp`, `password`: the `totp` and `password` modules.
+//! - `oidc`, `introspection`: the `oidc` and `introspection` modules. Their client awc is
+//!   built without TLS, enable one of its TLS features in the app for `https` endpoints.
 
+mod cache;
 mod cooke_session;
+mod error;
+mod handlers;
+mod impersonation;
+#[cfg(feature = "introspection")]
+pub mod introspection;
 mod loginmanager;
+mod next;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+#[cfg(feature = "password")]
+pub mod password;
+mod pattern;
+mod registry;
+mod require_login;
+#[cfg(any(feature = "totp", feature = "oidc"))]
+mod secure;
+#[cfg(feature = "test-utils")]
+pub mod test;
+#[cfg(feature = "totp")]
+pub mod totp;
+mod unauthorized;
 mod user;
+pub use crate::cache::{SharedUser, UserCache};
 pub use crate::cooke_session::CookieSession;
-pub use crate::loginmanager::{DecodeRequest, LoginInfo, LoginManager, LoginState};
-pub use crate::user::{UserMinix, UserWrap, UserWrapAuth};
+pub use crate::error::{DecodeError, LoginManagerError};
+pub use crate::handlers::{AuthError, Authenticator, LoginHandler, LogoutHandler};
+pub use crate::impersonation::{
+    BlockImpersonation, BlockImpersonationMiddleware, Impersonation, Impersonator,
+};
+pub use crate::loginmanager::{
+    AsyncDecodeRequest, BoxDecoder, DecodeRequest, LoginInfo, LoginManager, LoginState,
+    Principal, DEFAULT_REALM,
+};
+pub use crate::next::safe_next;
+pub use crate::pattern::PathPattern;
+pub use crate::registry::{
+    ActiveSession, MemoryRegistry, SessionLimit, SessionLimitExceeded, SessionRecord,
+    SessionRegistry, UserAgent, DEFAULT_IDLE_TIMEOUT,
+};
+pub use crate::require_login::{RequireLogin, RequireLoginMiddleware};
+pub use crate::unauthorized::{Reason, Unauthorized, UnauthorizedHandler};
+pub use crate::user::{
+    Account, Accounts, Anonymous, AnonymousUser, CurrentUser, PendingUser, SyncUserWrap,
+    SyncUserWrapAuth, UserMinix, UserWrap, UserWrapAuth,
+};
+use crate::loginmanager::LoginInfos;
+use crate::registry::Device;
 use actix_web::HttpMessage;
 pub use loginmanager_codegen::login_required;
 
-/// The method of user login
+/// The method of user login, in the realm of `U`. The other accounts are logged out.
+/// The rejection of the session limit is only logged, see `try_login`.
 pub fn login<U>(user: &dyn AsRef<U>, req: &actix_web::HttpRequest)
 where
     U: 'static + UserMinix,
 {
-    let mut extensions = req.extensions_mut();
-    let id = user.as_ref().get_id();
-    let id_str = serde_json::to_string(&id).ok();
-    extensions.insert(LoginInfo::new(id_str, LoginState::Login));
+    if let Err(err) = try_login(user, req) {
+        log::warn!("The login of the realm {:?} is rejected: {}", U::REALM, err);
+    }
+}
+
+/// Login like `login`, with the `max_sessions_per_user` of LoginManager.
+/// Return the number of the evicted sessions of the user, or the error if the limit rejects.
+/// ```ignore
+/// match loginmanager::try_login(&user, &req) {
+///     Ok(0) => HttpResponse::SeeOther().insert_header((LOCATION, "/")).finish(),
+///     Ok(_) => HttpResponse::SeeOther().insert_header((LOCATION, "/?signed_out_elsewhere=1")).finish(),
+///     Err(err) => HttpResponse::Forbidden().body(err.to_string()),
+/// }
+/// ```
+pub fn try_login<U>(
+    user: &dyn AsRef<U>,
+    req: &actix_web::HttpRequest,
+) -> Result<usize, SessionLimitExceeded>
+where
+    U: 'static + UserMinix,
+{
+    start_login(user, req, false)
+}
+
+/// Login the user in the realm of `U` with only the first factor, like `login`.
+/// The user extractors reject the user with `Reason::MfaPending` until `complete_login`,
+/// and LoginManager redirects to the `mfa_view`. Use `PendingUser<U>` to get the user.
+pub fn login_partial<U>(user: &dyn AsRef<U>, req: &actix_web::HttpRequest)
+where
+    U: 'static + UserMinix,
+{
+    if let Err(err) = try_login_partial(user, req) {
+        log::warn!("The login of the realm {:?} is rejected: {}", U::REALM, err);
+    }
 }
 
-/// The method of user logout
+/// `login_partial` with the session limit like `try_login`.
+pub fn try_login_partial<U>(
+    user: &dyn AsRef<U>,
+    req: &actix_web::HttpRequest,
+) -> Result<usize, SessionLimitExceeded>
+where
+    U: 'static + UserMinix,
+{
+    start_login(user, req, true)
+}
+
+fn start_login<U>(
+    user: &dyn AsRef<U>,
+    req: &actix_web::HttpRequest,
+    pending: bool,
+) -> Result<usize, SessionLimitExceeded>
+where
+    U: 'static + UserMinix,
+{
+    let id_str = serde_json::to_string(user.as_ref().get_id()).ok();
+    let evicted = match &id_str {
+        Some(id_str) => LoginInfos::get(req, U::REALM, |info| registry::admit(info, id_str))
+            .transpose()?
+            .unwrap_or(0),
+        None => 0,
+    };
+    forget_user::<U>(req);
+    let device = Device::of(req);
+    LoginInfos::update(req, U::REALM, |info| {
+        info.accounts = id_str.iter().cloned().collect();
+        info.impersonators.clear();
+        info.pending = pending;
+        info.key_str = id_str;
+        info.state = if pending {
+            LoginState::Partial
+        } else {
+            LoginState::Login
+        };
+        // The new login starts a new session in the registry.
+        registry::unregister(info);
+        info.rotate();
+        registry::register(info, device);
+    });
+    Ok(evicted)
+}
+
+/// Complete the login of `login_partial` after the second factor passed.
+/// Return false if no pending user in the realm of `U`.
+pub fn complete_login<U>(req: &actix_web::HttpRequest) -> bool
+where
+    U: 'static + UserMinix,
+{
+    forget_user::<U>(req);
+    LoginInfos::update(req, U::REALM, |info| {
+        if !info.pending || info.key_str.is_none() {
+            return false;
+        }
+        info.pending = false;
+        info.state = LoginState::Login;
+        info.rotate();
+        true
+    })
+}
+
+/// Login one more account in the realm of `U` and switch to it,
+/// the other accounts stay logged in. The rejection of the session limit is only logged,
+/// see `try_login_additional`.
+pub fn login_additional<U>(user: &dyn AsRef<U>, req: &actix_web::HttpRequest)
+where
+    U: 'static + UserMinix,
+{
+    if let Err(err) = try_login_additional(user, req) {
+        log::warn!("The login of the realm {:?} is rejected: {}", U::REALM, err);
+    }
+}
+
+/// `login_additional` with the session limit like `try_login`,
+/// the session is left as it is if the limit rejects.
+pub fn try_login_additional<U>(
+    user: &dyn AsRef<U>,
+    req: &actix_web::HttpRequest,
+) -> Result<usize, SessionLimitExceeded>
+where
+    U: 'static + UserMinix,
+{
+    let id_str = match serde_json::to_string(user.as_ref().get_id()) {
+        Ok(id_str) => id_str,
+        Err(_) => return Ok(0),
+    };
+    let evicted = LoginInfos::get(req, U::REALM, |info| registry::admit(info, &id_str))
+        .transpose()?
+        .unwrap_or(0);
+    forget_user::<U>(req);
+    let device = Device::of(req);
+    LoginInfos::update(req, U::REALM, |info| {
+        if info.pending {
+            info.accounts.clear();
+            info.pending = false;
+        }
+        if !info.accounts.contains(&id_str) {
+            info.accounts.push(id_str.clone());
+        }
+        info.key_str = Some(id_str);
+        info.state = LoginState::Login;
+        info.rotate();
+        registry::register(info, device);
+    });
+    Ok(evicted)
+}
+
+/// The sessions of the current user of the realm of `U`, the oldest first.
+/// It is empty without the `session_registry` of LoginManager. While impersonating,
+/// they are the sessions of the impersonator, like in `revoke_session`.
+/// ```ignore
+/// #[get("/account/sessions")]
+/// async fn sessions(req: HttpRequest, _: UserWrap<User>) -> HttpResponse {
+///     HttpResponse::Ok().json(loginmanager::list_sessions::<User>(&req))
+/// }
+/// ```
+pub fn list_sessions<U>(req: &actix_web::HttpRequest) -> Vec<ActiveSession>
+where
+    U: 'static + UserMinix,
+{
+    LoginInfos::get(req, U::REALM, registry::list).unwrap_or_default()
+}
+
+/// Log out the session of the current user of the realm of `U` by the `handle`
+/// of `list_sessions`. The current session logs out like `logout`. While impersonating,
+/// the current user is the impersonator, the impersonated user keeps the sessions.
+/// Return false if the user has no such session.
+pub fn revoke_session<U>(req: &actix_web::HttpRequest, handle: &str) -> bool
+where
+    U: 'static + UserMinix,
+{
+    let (revoked, current) = LoginInfos::update(req, U::REALM, |info| {
+        let revoked = registry::revoke(info, |session| registry::is_handle(&session.id, handle));
+        let current = info
+            .session_id
+            .as_ref()
+            .is_some_and(|id| revoked.contains(id));
+        if current {
+            info.accounts.clear();
+            info.impersonators.clear();
+            info.pending = false;
+            info.state = LoginState::Logout;
+        }
+        (!revoked.is_empty(), current)
+    });
+    if current {
+        forget_user::<U>(req);
+    }
+    revoked
+}
+
+/// Log out all the other sessions of the current user of the realm of `U`,
+/// like after the password change. Return the number of them.
+pub fn revoke_other_sessions<U>(req: &actix_web::HttpRequest) -> usize
+where
+    U: 'static + UserMinix,
+{
+    LoginInfos::get(req, U::REALM, |info| {
+        registry::revoke(info, |session| Some(&session.id) != info.session_id.as_ref()).len()
+    })
+    .unwrap_or(0)
+}
+
+/// Start a new session id in all the realms with a logged in user, for the privilege
+/// changes like the password change. The login functions rotate it already.
+pub fn rotate_session(req: &actix_web::HttpRequest) {
+    LoginInfos::update_all(req, |info| {
+        if info.key_str.is_none() {
+            return;
+        }
+        info.rotate();
+        if matches!(info.state, LoginState::Wait | LoginState::Ok) {
+            info.state = LoginState::Update;
+        }
+    });
+}
+
+/// Switch the active account of the realm of `U` to the `index` of `Accounts<U>`.
+/// Return false if no such account, or the request is impersonated.
+pub fn switch_account<U>(req: &actix_web::HttpRequest, index: usize) -> bool
+where
+    U: 'static + UserMinix,
+{
+    forget_user::<U>(req);
+    LoginInfos::update(req, U::REALM, |info| match info.accounts.get(index) {
+        Some(_) if !info.impersonators.is_empty() => false,
+        Some(key_str) => {
+            info.key_str = Some(key_str.clone());
+            info.state = LoginState::Update;
+            true
+        }
+        None => false,
+    })
+}
+
+/// The method of user logout, in the realm of `U`. All the accounts are logged out.
 pub fn logout<U>(user: &dyn AsRef<U>, req: &actix_web::HttpRequest)
 where
     U: 'static + UserMinix,
 {
-    let mut extensions = req.extensions_mut();
     let id = user.as_ref().get_id();
     let id_str = serde_json::to_string(&id).ok();
-    extensions.insert(LoginInfo::new(id_str, LoginState::Logout));
+    forget_user::<U>(req);
+    LoginInfos::update(req, U::REALM, |info| {
+        info.accounts.clear();
+        info.impersonators.clear();
+        info.pending = false;
+        info.key_str = id_str;
+        info.state = LoginState::Logout;
+        registry::unregister(info);
+    });
+}
+
+/// Logout one account in the realm of `U`. If it is the active one,
+/// switch to the first of the remaining accounts.
+pub fn logout_one<U>(user: &dyn AsRef<U>, req: &actix_web::HttpRequest)
+where
+    U: 'static + UserMinix,
+{
+    let id_str = match serde_json::to_string(user.as_ref().get_id()) {
+        Ok(id_str) => id_str,
+        Err(_) => return,
+    };
+    forget_user::<U>(req);
+    let device = Device::of(req);
+    LoginInfos::update(req, U::REALM, |info| {
+        info.accounts.retain(|key_str| key_str != &id_str);
+        if info.accounts.is_empty() {
+            info.key_str = Some(id_str);
+            info.state = LoginState::Logout;
+            registry::unregister(info);
+        } else {
+            if info.key_str.as_ref() == Some(&id_str) {
+                info.key_str = info.accounts.first().cloned();
+            }
+            info.state = LoginState::Update;
+            registry::register(info, device);
+        }
+    });
+}
+
+/// Impersonate the `target` user in the realm of `U`, the logged in user is kept
+/// in the session and restored by `stop_impersonating`. Check the permission of
+/// the logged in user before. Return false if no user logged in.
+///
+/// The other accounts stay logged in but none is active, and the session id is rotated.
+/// ```ignore
+/// #[get("/impersonate/{id}")]
+/// async fn impersonate(req: HttpRequest, _: UserWrapAuth<Admin>, id: web::Path<i32>) -> impl Responder {
+///     let target = User::get_user(&id, &req).await.ok_or_else(|| ErrorNotFound("no such user"))?;
+///     loginmanager::impersonate(&req, &UserWrap::from(target));
+///     Ok::<_, Error>(HttpResponse::Ok())
+/// }
+/// ```
+pub fn impersonate<U>(req: &actix_web::HttpRequest, target: &dyn AsRef<U>) -> bool
+where
+    U: 'static + UserMinix,
+{
+    let target = match serde_json::to_string(target.as_ref().get_id()) {
+        Ok(target) => target,
+        Err(_) => return false,
+    };
+    let original = LoginInfos::update(req, U::REALM, |info| {
+        if info.pending || matches!(info.state, LoginState::Logout) {
+            return None;
+        }
+        let original = info.key_str.clone()?;
+        info.key_str = Some(target.clone());
+        info.impersonators.push(original.clone());
+        info.state = LoginState::Update;
+        info.rotate();
+        Some(original)
+    });
+    match original {
+        Some(original) => {
+            forget_user::<U>(req);
+            impersonation::audit(req, U::REALM, Impersonation::Start, &original, &target);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stop the last impersonation in the realm of `U` and restore the user before it.
+/// Return false if the request is not impersonated.
+pub fn stop_impersonating<U>(req: &actix_web::HttpRequest) -> bool
+where
+    U: 'static + UserMinix,
+{
+    let stopped = LoginInfos::update(req, U::REALM, |info| {
+        let original = info.impersonators.pop()?;
+        let user = info.key_str.replace(original.clone()).unwrap_or_default();
+        info.state = LoginState::Update;
+        info.rotate();
+        Some((original, user))
+    });
+    match stopped {
+        Some((original, user)) => {
+            forget_user::<U>(req);
+            impersonation::audit(req, U::REALM, Impersonation::Stop, &original, &user);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Drop the cached user from the user cache of the realm of `U`,
+/// call it after the user is changed or deleted.
+pub fn invalidate_user<U>(req: &actix_web::HttpRequest, key: &U::Key)
+where
+    U: 'static + UserMinix,
+{
+    let cache = LoginInfos::config(req, Some(U::REALM)).and_then(|config| config.cache.clone());
+    if let Some(cache) = cache {
+        cache.invalidate_user::<U>(key);
+    }
+    forget_user::<U>(req);
+}
+
+/// Drop the user loaded by the request, the active account is changed.
+fn forget_user<U: 'static>(req: &actix_web::HttpRequest) {
+    let mut extensions = req.extensions_mut();
+    extensions.remove::<UserWrap<U>>();
+    extensions.remove::<SyncUserWrap<U>>();
 }
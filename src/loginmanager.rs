@@ -1,28 +1,177 @@
+use crate::cache::UserCache;
+use crate::error::{DecodeError, LoginManagerError};
+use crate::impersonation::{Impersonation, ImpersonationHook};
+use crate::pattern::PathPattern;
+use crate::registry::{self, SessionLimit, SessionRegistry};
+use crate::unauthorized::{Reason, Unauthorized, UnauthorizedHandler};
+use actix_web::body::EitherBody;
+use actix_web::cookie::Cookie;
 use actix_web::dev::{forward_ready, Service, Transform};
 use actix_web::HttpMessage;
 use actix_web::{
-    dev::{ServiceRequest, ServiceResponse},
-    http,
-    http::header::HeaderValue,
-    http::header::LOCATION,
-    Error,
+    dev::{ResponseHead, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue, ACCESS_CONTROL_REQUEST_METHOD, SET_COOKIE},
+    http::{Method, StatusCode},
+    Error, HttpRequest, HttpResponse,
 };
 use futures::{
-    future::{ok, Ready},
+    future::{err, ok, ready, LocalBoxFuture, Ready},
     Future,
 };
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::rc::Rc;
 
+const CLEAR_SITE_DATA: HeaderName = HeaderName::from_static("clear-site-data");
+
+/// The realm of a LoginManager and its users if not set.
+pub const DEFAULT_REALM: &str = "default";
+
+/// The synchronous decoder, like `CookieSession`. It is an `AsyncDecodeRequest`
+/// by the blanket implementation.
 pub trait DecodeRequest: Sized {
     fn decode(&self, req: &ServiceRequest) -> Option<String>;
-    fn update_<B>(&self, res: &mut ServiceResponse<B>) -> Result<(), Error> {
+
+    /// Decode the login info of the request, the default only has the key of `decode`.
+    fn decode_info(&self, req: &ServiceRequest) -> LoginInfo {
+        LoginInfo::new(self.decode(req), LoginState::Wait)
+    }
+
+    /// Update the response of the request with the login info of the LoginManager's
+    /// realm, `None` if the request did not get the login info.
+    fn update_(
+        &self,
+        _info: Option<&LoginInfo>,
+        _req: &HttpRequest,
+        _res: &mut ResponseHead,
+    ) -> Result<(), Error> {
         Ok(())
     }
 }
 
+/// The decoder of LoginManager, for the decoders need I/O like the server side
+/// stores, the token introspection or the API keys in the database.
+/// ```ignore
+/// impl AsyncDecodeRequest for ApiKeys {
+///     fn decode(&self, req: &ServiceRequest) -> LocalBoxFuture<'static, Result<Option<Principal>, DecodeError>> {
+///         let pool = self.0.clone();
+///         let api_key = req.headers().get("x-api-key").cloned();
+///         Box::pin(async move {
+///             let api_key = match api_key {
+///                 Some(api_key) => api_key,
+///                 None => return Ok(None),
+///             };
+///             let user_id = ApiKey::find_user(&pool, api_key.as_bytes())
+///                 .await
+///                 .map_err(|err| DecodeError::Unavailable(err.to_string()))?;
+///             Ok(user_id.map(|id| Principal::new(id.to_string())))
+///         })
+///     }
+/// }
+/// ```
+///
+/// It is object safe, see `LoginManager::boxed` for the decoders chosen at runtime.
+pub trait AsyncDecodeRequest {
+    /// Decode the user of the request, `None` for the anonymous. The error fails the request.
+    fn decode(
+        &self,
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Option<Principal>, DecodeError>>;
+
+    /// Update the response of the request with the login info of the LoginManager's
+    /// realm, `None` if the request did not get the login info.
+    fn update_<'a>(
+        &'a self,
+        _info: Option<&'a LoginInfo>,
+        _req: &'a HttpRequest,
+        _res: &'a mut ResponseHead,
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        Box::pin(ready(Ok(())))
+    }
+}
+
+/// The decoder chosen at runtime, see `LoginManager::boxed`.
+pub type BoxDecoder = Box<dyn AsyncDecodeRequest>;
+
+impl AsyncDecodeRequest for BoxDecoder {
+    fn decode(
+        &self,
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Option<Principal>, DecodeError>> {
+        (**self).decode(req)
+    }
+
+    fn update_<'a>(
+        &'a self,
+        info: Option<&'a LoginInfo>,
+        req: &'a HttpRequest,
+        res: &'a mut ResponseHead,
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        (**self).update_(info, req, res)
+    }
+}
+
+impl<D: DecodeRequest> AsyncDecodeRequest for D {
+    fn decode(
+        &self,
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Option<Principal>, DecodeError>> {
+        Box::pin(ready(Ok(self.decode_info(req).into_principal())))
+    }
+
+    fn update_<'a>(
+        &'a self,
+        info: Option<&'a LoginInfo>,
+        req: &'a HttpRequest,
+        res: &'a mut ResponseHead,
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        Box::pin(ready(DecodeRequest::update_(self, info, req, res)))
+    }
+}
+
+/// The user decoded from the request.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// The key of the active account, serialized by serde_json.
+    pub key_str: String,
+    /// The keys of all the logged in accounts, in login order.
+    pub accounts: Vec<String>,
+    /// The keys of the original users while impersonating, the real one first.
+    pub impersonators: Vec<String>,
+    /// The active account only passed the first factor, see `login_partial`.
+    pub pending: bool,
+    /// The id of the session, for the decoders of sessions.
+    pub session_id: Option<String>,
+}
+
+impl Principal {
+    pub fn new(key_str: String) -> Self {
+        Self {
+            accounts: vec![key_str.clone()],
+            key_str,
+            impersonators: Vec::new(),
+            pending: false,
+            session_id: None,
+        }
+    }
+}
+
+/// A new random session id, 32 alphanumeric characters.
+pub(crate) fn new_session_id() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
 pub enum LoginState {
     Login,
+    /// Login by `login_partial`, the second factor is pending.
+    Partial,
     Logout,
     Update,
     Ok,
@@ -31,75 +180,539 @@ pub enum LoginState {
 }
 
 pub struct LoginInfo {
+    /// The key of the active account.
     pub key_str: Option<String>,
     pub state: LoginState,
+    /// The keys of all the logged in accounts, in login order.
+    pub accounts: Vec<String>,
+    /// The keys of the original users while impersonating, the real one first.
+    pub impersonators: Vec<String>,
+    /// The active account only passed the first factor, see `login_partial`.
+    pub pending: bool,
+    /// The id of the session, a new one on login and `rotate_session`.
+    pub session_id: Option<String>,
+    /// The id before the rotation in this request. The server side stores delete
+    /// it when they save the new one, in the same transaction.
+    pub previous_session_id: Option<String>,
+    pub(crate) config: Rc<Config>,
 }
 
 impl LoginInfo {
     pub fn new(key_str: Option<String>, state: LoginState) -> Self {
-        Self { key_str, state }
+        Self {
+            accounts: key_str.iter().cloned().collect(),
+            key_str,
+            state,
+            impersonators: Vec::new(),
+            pending: false,
+            session_id: None,
+            previous_session_id: None,
+            config: Rc::new(Config::default()),
+        }
+    }
+
+    fn from_principal(principal: Option<Principal>) -> Self {
+        match principal {
+            Some(principal) => Self {
+                key_str: Some(principal.key_str),
+                accounts: principal.accounts,
+                impersonators: principal.impersonators,
+                pending: principal.pending,
+                session_id: principal.session_id,
+                ..Self::new(None, LoginState::Wait)
+            },
+            None => Self::new(None, LoginState::Wait),
+        }
+    }
+
+    fn into_principal(self) -> Option<Principal> {
+        Some(Principal {
+            key_str: self.key_str?,
+            accounts: self.accounts,
+            impersonators: self.impersonators,
+            pending: self.pending,
+            session_id: self.session_id,
+        })
+    }
+
+    /// Start a new session id. The previous one is the id the request came with,
+    /// even if it rotates more than once.
+    pub(crate) fn rotate(&mut self) {
+        let previous = self.session_id.replace(new_session_id());
+        if let (Some(old_id), Some(new_id)) = (&previous, &self.session_id) {
+            registry::rotate(&self.config, old_id, new_id);
+        }
+        if self.previous_session_id.is_none() {
+            self.previous_session_id = previous;
+        }
+    }
+
+    /// The index of the active account.
+    pub fn active(&self) -> Option<usize> {
+        let key_str = self.key_str.as_ref()?;
+        self.accounts.iter().position(|key| key == key_str)
+    }
+
+    /// The realm of the LoginManager.
+    pub fn realm(&self) -> &str {
+        &self.config.realm
+    }
+}
+
+/// The login infos of the request, one for each realm of the LoginManagers.
+#[derive(Default)]
+pub(crate) struct LoginInfos(HashMap<String, LoginInfo>);
+
+impl LoginInfos {
+    pub(crate) fn get<F, R>(req: &HttpRequest, realm: &str, f: F) -> Option<R>
+    where
+        F: FnOnce(&LoginInfo) -> R,
+    {
+        let extensions = req.extensions();
+        extensions.get::<Self>()?.0.get(realm).map(f)
+    }
+
+    /// Run `f` with the login infos of all the realms, like `update`.
+    pub(crate) fn update_all<F>(req: &HttpRequest, mut f: F)
+    where
+        F: FnMut(&mut LoginInfo),
+    {
+        let realms = match req.extensions().get::<Self>() {
+            Some(infos) => infos.0.keys().cloned().collect::<Vec<_>>(),
+            None => return,
+        };
+        for realm in realms {
+            Self::update(req, &realm, &mut f);
+        }
+    }
+
+    /// Run `f` with the login info of the realm, which is created if the request has not.
+    pub(crate) fn update<F, R>(req: &HttpRequest, realm: &str, f: F) -> R
+    where
+        F: FnOnce(&mut LoginInfo) -> R,
+    {
+        let mut extensions = req.extensions_mut();
+        if !extensions.contains::<Self>() {
+            extensions.insert(Self::default());
+        }
+        let infos = extensions.get_mut::<Self>().unwrap();
+        let info = infos.0.entry(realm.to_owned()).or_insert_with(|| {
+            let config = Config {
+                realm: realm.to_owned(),
+                ..Config::default()
+            };
+            LoginInfo {
+                config: Rc::new(config),
+                ..LoginInfo::new(None, LoginState::Wait)
+            }
+        });
+        f(info)
+    }
+
+    fn insert(req: &HttpRequest, info: LoginInfo) {
+        let realm = info.realm().to_owned();
+        Self::update(req, &realm, move |old| *old = info);
+    }
+
+    fn remove(req: &HttpRequest, realm: &str) -> Option<LoginInfo> {
+        req.extensions_mut().get_mut::<Self>()?.0.remove(realm)
+    }
+
+    /// The config of the realm. If no realm specified, it is the default realm
+    /// or the only realm of the request.
+    pub(crate) fn config(req: &HttpRequest, realm: Option<&str>) -> Option<Rc<Config>> {
+        let extensions = req.extensions();
+        let infos = &extensions.get::<Self>()?.0;
+        let info = match realm {
+            Some(realm) => infos.get(realm),
+            None if infos.len() == 1 => infos.values().next(),
+            None => infos.get(DEFAULT_REALM),
+        };
+        info.map(|info| info.config.clone())
+    }
+}
+
+/// The settings of LoginManager, which are also available to the handlers
+/// in the login info.
+pub(crate) struct Config {
+    pub(crate) realm: String,
+    pub(crate) cache: Option<UserCache>,
+    pub(crate) next_param: String,
+    pub(crate) next_default: String,
+    pub(crate) on_impersonation: Option<ImpersonationHook>,
+    pub(crate) registry: Option<Rc<dyn SessionRegistry>>,
+    pub(crate) session_limit: Option<(usize, SessionLimit)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            realm: DEFAULT_REALM.to_owned(),
+            cache: None,
+            next_param: "next".to_owned(),
+            next_default: "/".to_owned(),
+            on_impersonation: None,
+            registry: None,
+            session_limit: None,
+        }
     }
 }
 
 struct Inner<D>
 where
-    D: DecodeRequest,
+    D: AsyncDecodeRequest,
 {
     decoder: D,
-    login_view: HeaderValue,
-    redirect: bool,
+    login_view: String,
+    mfa_view: Option<String>,
+    unauthorized: UnauthorizedHandler,
+    exclude: Vec<PathPattern>,
+    config: Rc<Config>,
+    /// The removal cookies sent on logout.
+    clear_cookies: Vec<HeaderValue>,
+    clear_site_data: Option<HeaderValue>,
+    /// The error of the infallible builders, reported by `new_transform`.
+    error: Option<LoginManagerError>,
+}
+
+impl<D> Inner<D>
+where
+    D: AsyncDecodeRequest,
+{
+    /// The view unauthorized requests redirect to, the mfa view for the pending users.
+    fn view(&self, reason: Reason) -> &str {
+        match (reason, &self.mfa_view) {
+            (Reason::MfaPending, Some(mfa_view)) => mfa_view,
+            _ => &self.login_view,
+        }
+    }
+
+    /// The path of the view, `None` if it is on another origin.
+    fn view_path(view: &str) -> Option<&str> {
+        let path = view.split(['?', '#']).next()?;
+        if path.starts_with('/') && !path.starts_with("//") {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// The excluded paths and the CORS preflight requests skip the loginmanager.
+    fn is_excluded(&self, req: &ServiceRequest) -> bool {
+        let preflight = req.method() == Method::OPTIONS
+            && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+        preflight || self.exclude.iter().any(|pattern| pattern.is_match(req.path()))
+    }
+
+    /// Write the login info of the realm to the response by the decoder,
+    /// the response is replaced by the error if it fails.
+    async fn update<B>(
+        &self,
+        mut res: ServiceResponse<EitherBody<B>>,
+    ) -> ServiceResponse<EitherBody<B>> {
+        let req = res.request().clone();
+        let info = LoginInfos::remove(&req, &self.config.realm);
+        let logout = matches!(info, Some(LoginInfo { state: LoginState::Logout, .. }));
+        let head = res.response_mut().head_mut();
+        match self.decoder.update_(info.as_ref(), &req, head).await {
+            Ok(()) if logout => {
+                let headers = res.headers_mut();
+                for cookie in &self.clear_cookies {
+                    headers.append(SET_COOKIE, cookie.clone());
+                }
+                if let Some(clear_site_data) = &self.clear_site_data {
+                    headers.insert(CLEAR_SITE_DATA, clear_site_data.clone());
+                }
+                res
+            }
+            Ok(()) => res,
+            Err(err) => {
+                log::error!("LoginManager of the realm {:?}: {}", self.config.realm, err);
+                res.into_response(HttpResponse::from_error(err))
+                    .map_into_right_body()
+            }
+        }
+    }
+
+    /// The view with the percent-encoded `next` query of the request.
+    fn view_url(&self, login_view: &str, req: &HttpRequest) -> String {
+        let next = match req.uri().path_and_query() {
+            Some(path) => path.as_str(),
+            None => req.path(),
+        };
+        let separator = if login_view.contains('?') { '&' } else { '?' };
+        format!(
+            "{}{}{}={}",
+            login_view,
+            separator,
+            urlencoding::encode(&self.config.next_param),
+            urlencoding::encode(next)
+        )
+    }
+}
+
+/// Check the url of a view. A relative view redirects to itself under the
+/// protected path again and again, and the url is sent in the `Location` header
+/// as it is, so it must be percent-encoded already.
+fn view(url: &str) -> Result<String, LoginManagerError> {
+    if !url.bytes().all(|byte| byte.is_ascii_graphic()) {
+        return Err(LoginManagerError::InvalidUrl(url.to_owned()));
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let is_path = path.starts_with('/') && !path.starts_with("//");
+    if !is_path && !url.contains("://") {
+        return Err(LoginManagerError::RelativeView(url.to_owned()));
+    }
+    Ok(url.to_owned())
 }
 
 /// LoginManager<D> is implemented as a middleware.   
-/// - `D` the type of DecodeRequest. It decode the key_string from request.  
+/// - `D` the type of AsyncDecodeRequest. It decode the key_string from request.  
+///
+/// The session is written to the responses, the errors of the handlers included.
+/// The errors of the middlewares inside LoginManager have no request left, so the
+/// session and the 401 of them are left as they are, wrap those middlewares outside.
 pub struct LoginManager<D>(Rc<Inner<D>>)
 where
-    D: DecodeRequest;
+    D: AsyncDecodeRequest;
+
+impl LoginManager<BoxDecoder> {
+    /// The LoginManager of the boxed decoder, to choose the decoder at runtime.
+    /// ```ignore
+    /// let manager = match config.auth.as_str() {
+    ///     "introspection" => LoginManager::boxed(Introspection::new(&config.endpoint, "api", &config.secret)),
+    ///     _ => LoginManager::boxed(CookieSession::new(config.key.as_bytes())),
+    /// };
+    /// ```
+    pub fn boxed<D: AsyncDecodeRequest + 'static>(decoder: D) -> Self {
+        Self::new(Box::new(decoder))
+    }
+}
 
 impl<D> LoginManager<D>
 where
-    D: DecodeRequest,
+    D: AsyncDecodeRequest,
 {
     pub fn new(decoder: D) -> Self
     where
-        D: DecodeRequest,
+        D: AsyncDecodeRequest,
     {
         Self(Rc::new(Inner {
             decoder,
-            login_view: HeaderValue::from_str("/login").unwrap(),
-            redirect: true,
+            login_view: "/login".to_owned(),
+            mfa_view: None,
+            unauthorized: UnauthorizedHandler::Negotiate,
+            exclude: Vec::new(),
+            config: Rc::new(Config::default()),
+            clear_cookies: Vec::new(),
+            clear_site_data: None,
+            error: None,
         }))
     }
 
+    /// The LoginManager is not `Clone` and only shares itself in `new_transform`,
+    /// so it is always unique while building.
+    fn inner_mut(&mut self) -> &mut Inner<D> {
+        Rc::get_mut(&mut self.0).expect("LoginManager is configured after it is used")
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        Rc::get_mut(&mut self.inner_mut().config).expect("LoginManager is configured after it is used")
+    }
+
     /// Set false, not redirect when user is not authenticated. Default true.
-    pub fn redirect(mut self, redirect: bool) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().redirect = redirect;
+    pub fn redirect(self, redirect: bool) -> Self {
+        if redirect {
+            self.unauthorized_handler(UnauthorizedHandler::Negotiate)
+        } else {
+            self.unauthorized_handler(UnauthorizedHandler::Status)
+        }
+    }
+
+    /// Set the response of unauthorized requests, default `UnauthorizedHandler::Negotiate`.
+    ///
+    /// A scope can override it with `.app_data(UnauthorizedHandler::...)`.
+    pub fn unauthorized_handler(mut self, handler: UnauthorizedHandler) -> Self {
+        self.inner_mut().unauthorized = handler;
         self
     }
 
-    /// Set the login url redirect, default '/login'.
-    pub fn login_view(mut self, login_view: String) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().login_view = HeaderValue::from_str(&login_view).unwrap();
+    /// Set the realm, default `DEFAULT_REALM`.
+    ///
+    /// The LoginManagers of different realms keep their users apart, `UserWrap<U>`
+    /// only reads the LoginManager of `U::REALM`. Use different cookie names for them.
+    /// ```ignore
+    /// App::new()
+    ///     .wrap(LoginManager::new(CookieSession::new(&key).name("_admin")).realm("admin"))
+    ///     .wrap(LoginManager::new(CookieSession::new(&key).name("_customer")).realm("customer"))
+    /// ```
+    pub fn realm(mut self, realm: &str) -> Self {
+        self.config_mut().realm = realm.to_owned();
+        self
+    }
+
+    /// Cache the users across the requests and the workers, see `UserCache`.
+    pub fn user_cache(mut self, cache: UserCache) -> Self {
+        self.config_mut().cache = Some(cache);
+        self
+    }
+
+    /// Record the sessions on the server, see `MemoryRegistry`. The sessions not
+    /// in the registry are logged out, the users log in again after it is enabled.
+    pub fn session_registry<R: SessionRegistry + 'static>(mut self, registry: R) -> Self {
+        self.config_mut().registry = Some(Rc::new(registry));
+        self
+    }
+
+    /// Allow at most `limit` sessions of a user, it needs the `session_registry`.
+    /// It applies to `try_login`, `try_login_partial` and `try_login_additional`,
+    /// `login` only logs the rejection.
+    pub fn max_sessions_per_user(mut self, limit: usize, policy: SessionLimit) -> Self {
+        self.config_mut().session_limit = Some((limit.max(1), policy));
+        self
+    }
+
+    /// Add the public paths, which are not decoded and never redirected,
+    /// like the static files and the health checks. With the `session_registry`,
+    /// only the session id is decoded for the login and logout there.
+    ///
+    /// The CORS preflight requests are always public. Keep the login view out of them,
+    /// the redirect skips it already, and `login_additional` there needs the accounts.
+    pub fn exclude<I, P>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathPattern>,
+    {
+        let inner = self.inner_mut();
+        inner.exclude.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set the name of the query parameter holding the url before login, default 'next'.
+    pub fn next_param(mut self, name: &str) -> Self {
+        self.config_mut().next_param = name.to_owned();
         self
     }
+
+    /// Set the url `safe_next` falls back to, default '/'.
+    pub fn next_default(mut self, url: &str) -> Self {
+        self.config_mut().next_default = url.to_owned();
+        self
+    }
+
+    /// Set the audit hook of impersonation, it is called with the keys of
+    /// the impersonator and the impersonated user.
+    /// ```ignore
+    /// LoginManager::new(CookieSession::new(&[0; 32])).on_impersonation(|_req, event, admin, user| {
+    ///     log::warn!("{:?} impersonating: {} as {}", event, admin, user);
+    /// })
+    /// ```
+    pub fn on_impersonation<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&HttpRequest, Impersonation, &str, &str) + 'static,
+    {
+        self.config_mut().on_impersonation = Some(Rc::new(hook));
+        self
+    }
+
+    /// Keep the first error of the builders for `new_transform`.
+    fn or_error(mut self, result: Result<(), LoginManagerError>) -> Self {
+        if let Err(err) = result {
+            self.inner_mut().error.get_or_insert(err);
+        }
+        self
+    }
+
+    /// Set the login url redirect, default '/login'. The invalid url fails
+    /// the app on start, see `try_login_view` to check it here.
+    pub fn login_view(mut self, login_view: String) -> Self {
+        let result = view(&login_view).map(|view| self.inner_mut().login_view = view);
+        self.or_error(result)
+    }
+
+    /// Set the login url redirect, it must be a path or an absolute url,
+    /// percent-encoded like `/%E7%99%BB%E5%BD%95`.
+    pub fn try_login_view(mut self, login_view: &str) -> Result<Self, LoginManagerError> {
+        self.inner_mut().login_view = view(login_view)?;
+        Ok(self)
+    }
+
+    /// Set the second factor url the pending users of `login_partial` redirect to,
+    /// default the login view. The invalid url fails the app on start like `login_view`.
+    pub fn mfa_view(mut self, mfa_view: String) -> Self {
+        let result = view(&mfa_view).map(|view| self.inner_mut().mfa_view = Some(view));
+        self.or_error(result)
+    }
+
+    /// Set the second factor url, it must be a path or an absolute url.
+    pub fn try_mfa_view(mut self, mfa_view: &str) -> Result<Self, LoginManagerError> {
+        self.inner_mut().mfa_view = Some(view(mfa_view)?);
+        Ok(self)
+    }
+
+    /// Remove the cookie on logout too, like the remember-me, CSRF or flash cookies.
+    /// The path and domain must be the ones of the cookie, the path is '/' if not set.
+    /// ```ignore
+    /// LoginManager::new(CookieSession::new(&key))
+    ///     .clear_cookie(Cookie::new("csrf", ""))
+    ///     .clear_cookie(Cookie::build("remember_me", "").path("/account").finish())
+    /// ```
+    pub fn clear_cookie(mut self, mut cookie: Cookie<'static>) -> Self {
+        if cookie.path().is_none() {
+            cookie.set_path("/");
+        }
+        cookie.make_removal();
+        let result = HeaderValue::from_str(&cookie.encoded().to_string())
+            .map(|cookie| self.inner_mut().clear_cookies.push(cookie))
+            .map_err(|_| LoginManagerError::InvalidHeader(cookie.to_string()));
+        self.or_error(result)
+    }
+
+    /// Send the `Clear-Site-Data` header on logout with the types, like
+    /// `&["cache", "cookies", "storage"]`. The browsers only accept it over https.
+    pub fn clear_site_data(mut self, types: &[&str]) -> Self {
+        let value = types
+            .iter()
+            .map(|ty| format!("\"{}\"", ty))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let valid = types
+            .iter()
+            .all(|ty| ty.chars().all(|c| c.is_ascii_alphanumeric() || c == '*'));
+        let result = HeaderValue::from_str(&value)
+            .ok()
+            .filter(|_| valid)
+            .map(|value| self.inner_mut().clear_site_data = Some(value))
+            .ok_or(LoginManagerError::InvalidHeader(value));
+        self.or_error(result)
+    }
 }
 
 impl<S, B, D: 'static> Transform<S, ServiceRequest> for LoginManager<D>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
-    D: DecodeRequest,
+    D: AsyncDecodeRequest,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = LoginManagerMiddleware<S, D>;
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
+        if let Some(error) = &self.0.error {
+            log::error!("LoginManager: {}", error);
+            return err(());
+        }
+        let config = &self.0.config;
+        if config.session_limit.is_some() && config.registry.is_none() {
+            log::error!("LoginManager: {}", LoginManagerError::MissingRegistry);
+            return err(());
+        }
         ok(LoginManagerMiddleware {
-            service,
+            service: Rc::new(service),
             inner: self.0.clone(),
         })
     }
@@ -107,20 +720,20 @@ where
 
 pub struct LoginManagerMiddleware<S, D>
 where
-    D: DecodeRequest,
+    D: AsyncDecodeRequest,
 {
-    service: S,
+    service: Rc<S>,
     inner: Rc<Inner<D>>,
 }
 
 impl<S, B, D: 'static> Service<ServiceRequest> for LoginManagerMiddleware<S, D>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
-    D: DecodeRequest,
+    D: AsyncDecodeRequest,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>+'static>>;
 
@@ -128,37 +741,84 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let inner = self.inner.clone();
-        let key_str = inner.decoder.decode(&req);
-        req.extensions_mut()
-            .insert(LoginInfo::new(key_str, LoginState::Wait));
-        let fut = self.service.call(req);
+        let service = self.service.clone();
+        if inner.is_excluded(&req) {
+            return Box::pin(async move {
+                // Not decoded, but the extractors know the LoginManager is there.
+                let mut info = LoginInfo {
+                    config: inner.config.clone(),
+                    ..LoginInfo::new(None, LoginState::Wait)
+                };
+                // The login and logout on the public paths still replace the session
+                // in the registry, like the usual excluded login view.
+                if inner.config.registry.is_some() {
+                    if let Ok(Some(principal)) = inner.decoder.decode(&req).await {
+                        info.session_id = principal.session_id;
+                    }
+                }
+                LoginInfos::insert(req.request(), info);
+                let res = service.call(req).await?.map_into_left_body();
+                Ok(inner.update(res).await)
+            });
+        }
         Box::pin(async move {
-            let res = fut.await.map(|mut res| {
-                inner.decoder.update_(&mut res);
-                if inner.redirect && res.status().as_u16() == 401 {
-                    res.response_mut().head_mut().status = http::StatusCode::FOUND;
-                    let mut path = String::new();
-                    let req = res.request();
-                    if inner.redirect {
-                        path.push_str(req.path());
-                        if req.query_string().len() > 0 {
-                            path.push_str("%3F");
-                            path.push_str(
-                                &req.query_string().replace("&", "%26").replace("=", "%3d"),
-                            );
-                        }
+            // The login info injected by the tests is kept.
+            if LoginInfos::get(req.request(), &inner.config.realm, |_| ()).is_some() {
+                LoginInfos::update(req.request(), &inner.config.realm, |info| {
+                    info.config = inner.config.clone();
+                });
+            } else {
+                let principal = match inner.decoder.decode(&req).await {
+                    Ok(principal) => principal,
+                    Err(err) => {
+                        log::error!("LoginManager of the realm {:?}: {}", inner.config.realm, err);
+                        return Ok(req.error_response(err).map_into_right_body());
                     }
-                    let headervalue = if path.len() > 0 {
-                        let url = format!("{}?next={}", inner.login_view.to_str().unwrap(), path);
-                        HeaderValue::from_str(&url).unwrap()
-                    } else {
-                        inner.login_view.clone()
+                };
+                let mut info = LoginInfo {
+                    config: inner.config.clone(),
+                    ..LoginInfo::from_principal(principal)
+                };
+                // Evicted or revoked, the session is removed.
+                if info.key_str.is_some() && !registry::check(&info) {
+                    info = LoginInfo {
+                        config: inner.config.clone(),
+                        ..LoginInfo::new(None, LoginState::Logout)
                     };
-                    res.headers_mut().insert(LOCATION, headervalue);
+                }
+                LoginInfos::insert(req.request(), info);
+            }
+            let res = service.call(req).await?.map_into_left_body();
+            let error = res
+                .response()
+                .error()
+                .and_then(|err| err.as_error::<Unauthorized>());
+            // The 401 of other realms are left to their LoginManagers.
+            let is_other_realm = error
+                .and_then(|err| err.realm())
+                .is_some_and(|realm| realm != inner.config.realm);
+            let reason = error.map_or(Reason::Unauthenticated, |err| err.reason());
+            let view = inner.view(reason);
+            let is_view = Inner::<D>::view_path(view) == Some(res.request().path());
+            // Redirecting the view to itself loops forever, keep the 401.
+            let res = if res.status() == StatusCode::UNAUTHORIZED
+                && !is_view
+                && !is_other_realm
+            {
+                let req = res.request();
+                let login_url = inner.view_url(view, req);
+                let response = match req.app_data::<UnauthorizedHandler>() {
+                    Some(handler) => handler.respond(req, reason, &login_url),
+                    None => inner.unauthorized.respond(req, reason, &login_url),
                 };
+                match response {
+                    Some(response) => res.into_response(response).map_into_right_body(),
+                    None => res,
+                }
+            } else {
                 res
-            })?;
-            Ok(res)
+            };
+            Ok(inner.update(res).await)
         })
     }
-}
\ No newline at end of file
+}
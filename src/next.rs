@@ -0,0 +1,41 @@
+use crate::loginmanager::{Config, LoginInfos};
+use actix_web::{web, HttpRequest};
+use std::collections::HashMap;
+
+/// Return the `next` url of the request if it is safe to redirect, otherwise the default url.
+///
+/// Only the same-origin paths are safe, absolute urls like `https://evil.com` and
+/// scheme-relative urls like `//evil.com` are rejected to prevent the open redirect.
+/// The parameter name and the default url are set by `LoginManager::next_param`
+/// and `LoginManager::next_default`.
+/// ```ignore
+/// async fn login(req: HttpRequest, form: web::Form<LoginForm>) -> impl Responder {
+///     let user = todo!(); // check the form
+///     loginmanager::login(&user, &req);
+///     HttpResponse::Found()
+///         .insert_header((LOCATION, loginmanager::safe_next(&req)))
+///         .finish()
+/// }
+/// ```
+pub fn safe_next(req: &HttpRequest) -> String {
+    let (param, default) = match LoginInfos::config(req, None) {
+        Some(config) => (config.next_param.clone(), config.next_default.clone()),
+        None => {
+            let config = Config::default();
+            (config.next_param, config.next_default)
+        }
+    };
+    web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|mut query| query.0.remove(&param))
+        .filter(|next| is_safe_next(next))
+        .unwrap_or(default)
+}
+
+/// Whether the url is a same-origin path.
+pub(crate) fn is_safe_next(next: &str) -> bool {
+    next.starts_with('/')
+        && !next.starts_with("//")
+        && !next.contains('\\')
+        && !next.chars().any(char::is_control)
+}
@@ -0,0 +1,421 @@
+//! OpenID Connect login with the authorization code flow and PKCE, enabled by the `oidc` feature.
+//!
+//! The state, nonce and PKCE verifier of the flow are kept in a short-lived private cookie,
+//! not in the session: the decoders of LoginManager write only the login info, and the
+//! decoders of the bearer tokens have no session to write to at all.
+//! The ID token is verified with the JWKS of the provider, then the claims are mapped to
+//! the user, who is logged in by `loginmanager::login`.
+//! ```ignore
+//! let provider = OidcProvider::new("https://accounts.example.com", "client-id", "https://app.example.com/oidc/callback", &[0; 32])
+//!     .client_secret("client-secret")
+//!     .discover()
+//!     .await?;
+//! let provider = web::Data::new(provider);
+//!
+//! async fn oidc_login(req: HttpRequest, provider: web::Data<OidcProvider>) -> HttpResponse {
+//!     provider.authorize(&req)
+//! }
+//!
+//! async fn oidc_callback(req: HttpRequest, provider: web::Data<OidcProvider>) -> Result<HttpResponse, OidcError> {
+//!     provider
+//!         .callback(&req, |claims| async move { User::find_by_email(claims.email?).await })
+//!         .await
+//! }
+//! ```
+use crate::next::safe_next;
+use crate::secure::secure_eq;
+use crate::user::{UserMinix, UserWrap};
+use actix_web::cookie::{time::Duration, Cookie, CookieJar, Key, SameSite};
+use actix_web::http::header::LOCATION;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+
+/// The errors of the OpenID Connect flow.
+#[derive(Debug)]
+pub enum OidcError {
+    /// The provider returned an error to the callback, like `access_denied`.
+    Provider(String),
+    /// The callback has no flow cookie or its state does not match.
+    InvalidState,
+    /// The request to the provider failed.
+    Request(String),
+    /// The ID token is invalid.
+    InvalidToken(String),
+    /// The user mapping callback found no user for the claims.
+    UnknownUser,
+    /// The user has `max_sessions_per_user` sessions with `SessionLimit::Reject`.
+    TooManySessions,
+}
+
+impl fmt::Display for OidcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OidcError::Provider(err) => write!(f, "The provider returned an error: {}", err),
+            OidcError::InvalidState => f.write_str("The login state is invalid."),
+            OidcError::Request(err) => write!(f, "The request to the provider failed: {}", err),
+            OidcError::InvalidToken(err) => write!(f, "The ID token is invalid: {}", err),
+            OidcError::UnknownUser => f.write_str("No user for the account."),
+            OidcError::TooManySessions => f.write_str("The user has too many sessions."),
+        }
+    }
+}
+
+impl std::error::Error for OidcError {}
+
+impl ResponseError for OidcError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            OidcError::Provider(_) | OidcError::InvalidState => StatusCode::BAD_REQUEST,
+            OidcError::Request(_) => StatusCode::BAD_GATEWAY,
+            OidcError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            OidcError::UnknownUser | OidcError::TooManySessions => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+/// The claims of the verified ID token.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Claims {
+    pub iss: String,
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    nonce: Option<String>,
+    /// The other claims.
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+/// The flow saved in the cookie between `authorize` and `callback`.
+#[derive(Serialize, Deserialize)]
+struct Flow {
+    state: String,
+    nonce: String,
+    verifier: String,
+    next: String,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The S256 code challenge of the PKCE verifier.
+fn code_challenge(verifier: &str) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.input_str(verifier);
+    let mut hash = [0u8; 32];
+    sha256.result(&mut hash);
+    URL_SAFE_NO_PAD.encode(hash)
+}
+
+/// The OpenID Connect provider, the relying party client of it.
+/// It can be shared by the workers, create it outside of the app factory.
+pub struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    key: Key,
+    cookie_name: String,
+    secure: bool,
+    jwks: Mutex<Option<JwkSet>>,
+}
+
+impl OidcProvider {
+    /// The `key` encrypts the flow cookie. The endpoints default to the ones under
+    /// the issuer, set them by `endpoints` or `discover`.
+    pub fn new(issuer: &str, client_id: &str, redirect_uri: &str, key: &[u8]) -> Self {
+        let issuer = issuer.trim_end_matches('/');
+        Self {
+            issuer: issuer.to_owned(),
+            client_id: client_id.to_owned(),
+            client_secret: None,
+            redirect_uri: redirect_uri.to_owned(),
+            scopes: vec![
+                "openid".to_owned(),
+                "email".to_owned(),
+                "profile".to_owned(),
+            ],
+            authorization_endpoint: format!("{}/authorize", issuer),
+            token_endpoint: format!("{}/token", issuer),
+            jwks_uri: format!("{}/jwks", issuer),
+            key: Key::derive_from(key),
+            cookie_name: "_oidc".to_owned(),
+            secure: true,
+            jwks: Mutex::new(None),
+        }
+    }
+
+    /// The secret of the confidential client, sent by HTTP basic authentication.
+    pub fn client_secret(mut self, client_secret: &str) -> Self {
+        self.client_secret = Some(client_secret.to_owned());
+        self
+    }
+
+    /// Set the scopes, default `openid email profile`. `openid` is always requested.
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = vec!["openid".to_owned()];
+        self.scopes.extend(
+            scopes
+                .iter()
+                .filter(|scope| **scope != "openid")
+                .map(|scope| scope.to_string()),
+        );
+        self
+    }
+
+    pub fn endpoints(mut self, authorization: &str, token: &str, jwks: &str) -> Self {
+        self.authorization_endpoint = authorization.to_owned();
+        self.token_endpoint = token.to_owned();
+        self.jwks_uri = jwks.to_owned();
+        self
+    }
+
+    /// The name of the flow cookie, default '_oidc'.
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Set the endpoints by the metadata at `/.well-known/openid-configuration` of the issuer.
+    pub async fn discover(self) -> Result<Self, OidcError> {
+        let url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let metadata = awc::Client::default()
+            .get(url)
+            .send()
+            .await
+            .map_err(|err| OidcError::Request(err.to_string()))?
+            .json::<Metadata>()
+            .await
+            .map_err(|err| OidcError::Request(err.to_string()))?;
+        Ok(self.endpoints(
+            &metadata.authorization_endpoint,
+            &metadata.token_endpoint,
+            &metadata.jwks_uri,
+        ))
+    }
+
+    fn flow_cookie(&self, value: String, max_age: Duration) -> Cookie<'static> {
+        let mut cookie = Cookie::new(self.cookie_name.clone(), value);
+        cookie.set_path("/");
+        cookie.set_secure(self.secure);
+        cookie.set_http_only(true);
+        // Lax, the callback is a top-level navigation from the provider.
+        cookie.set_same_site(SameSite::Lax);
+        cookie.set_max_age(max_age);
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.key).add(cookie);
+        jar.delta().next().unwrap().clone()
+    }
+
+    fn read_flow(&self, req: &HttpRequest) -> Option<Flow> {
+        let mut jar = CookieJar::new();
+        jar.add_original(req.cookie(&self.cookie_name)?);
+        let cookie = jar.private(&self.key).get(&self.cookie_name)?;
+        serde_json::from_str(cookie.value()).ok()
+    }
+
+    /// Redirect to the provider to login, the `next` query of the request
+    /// is where the callback redirects to at last.
+    pub fn authorize(&self, req: &HttpRequest) -> HttpResponse {
+        let flow = Flow {
+            state: random_string(),
+            nonce: random_string(),
+            verifier: random_string(),
+            next: safe_next(req),
+        };
+        let separator = if self.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let url = format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}\
+             &code_challenge={}&code_challenge_method=S256",
+            self.authorization_endpoint,
+            separator,
+            urlencoding::encode(&self.client_id),
+            urlencoding::encode(&self.redirect_uri),
+            urlencoding::encode(&self.scopes.join(" ")),
+            flow.state,
+            flow.nonce,
+            code_challenge(&flow.verifier)
+        );
+        let value = serde_json::to_string(&flow).unwrap_or_default();
+        HttpResponse::Found()
+            .insert_header((LOCATION, url))
+            .cookie(self.flow_cookie(value, Duration::minutes(10)))
+            .finish()
+    }
+
+    /// Handle the redirect back from the provider. Exchange the code for the ID token,
+    /// verify it, map the claims to the user by `map_user` and login the user.
+    pub async fn callback<U, F, Fut>(
+        &self,
+        req: &HttpRequest,
+        map_user: F,
+    ) -> Result<HttpResponse, OidcError>
+    where
+        U: UserMinix + 'static,
+        F: FnOnce(Claims) -> Fut,
+        Fut: Future<Output = Option<U>>,
+    {
+        let query = web::Query::<CallbackQuery>::from_query(req.query_string())
+            .map_err(|_| OidcError::InvalidState)?;
+        if let Some(error) = &query.error {
+            let description = query.error_description.as_deref().unwrap_or_default();
+            return Err(OidcError::Provider(
+                format!("{} {}", error, description).trim().to_owned(),
+            ));
+        }
+        let flow = self.read_flow(req).ok_or(OidcError::InvalidState)?;
+        let state = query.state.as_deref().unwrap_or_default();
+        if !secure_eq(state, &flow.state) {
+            return Err(OidcError::InvalidState);
+        }
+        let code = query.code.as_deref().ok_or(OidcError::InvalidState)?;
+
+        let id_token = self.exchange(code, &flow.verifier).await?;
+        let claims = self.verify(&id_token).await?;
+        if !claims
+            .nonce
+            .as_deref()
+            .is_some_and(|nonce| secure_eq(nonce, &flow.nonce))
+        {
+            return Err(OidcError::InvalidToken("nonce mismatch".to_owned()));
+        }
+        let user = map_user(claims).await.ok_or(OidcError::UnknownUser)?;
+        crate::try_login(&UserWrap::from(user), req).map_err(|_| OidcError::TooManySessions)?;
+
+        Ok(HttpResponse::SeeOther()
+            .insert_header((LOCATION, flow.next))
+            .cookie(self.flow_cookie(String::new(), Duration::ZERO))
+            .finish())
+    }
+
+    /// Exchange the authorization code for the ID token.
+    async fn exchange(&self, code: &str, verifier: &str) -> Result<String, OidcError> {
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", verifier),
+        ];
+        let mut request = awc::Client::default().post(&self.token_endpoint);
+        if let Some(secret) = &self.client_secret {
+            request = request.basic_auth(&self.client_id, secret);
+        }
+        let mut response = request
+            .send_form(&form)
+            .await
+            .map_err(|err| OidcError::Request(err.to_string()))?;
+        if !response.status().is_success() {
+            return Err(OidcError::Request(format!(
+                "token endpoint returned {}",
+                response.status()
+            )));
+        }
+        let tokens = response
+            .json::<TokenResponse>()
+            .await
+            .map_err(|err| OidcError::Request(err.to_string()))?;
+        tokens
+            .id_token
+            .ok_or_else(|| OidcError::InvalidToken("no id_token".to_owned()))
+    }
+
+    async fn fetch_jwks(&self) -> Result<JwkSet, OidcError> {
+        let jwks = awc::Client::default()
+            .get(&self.jwks_uri)
+            .send()
+            .await
+            .map_err(|err| OidcError::Request(err.to_string()))?
+            .json::<JwkSet>()
+            .await
+            .map_err(|err| OidcError::Request(err.to_string()))?;
+        *self.jwks.lock().unwrap_or_else(|err| err.into_inner()) = Some(jwks.clone());
+        Ok(jwks)
+    }
+
+    /// Verify the signature, issuer, audience and expiration of the ID token.
+    async fn verify(&self, id_token: &str) -> Result<Claims, OidcError> {
+        let invalid = |err: jsonwebtoken::errors::Error| OidcError::InvalidToken(err.to_string());
+        let header = jsonwebtoken::decode_header(id_token).map_err(invalid)?;
+        // The keys of JWKS are public, the symmetric algorithms are not allowed.
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(OidcError::InvalidToken("symmetric algorithm".to_owned()));
+        }
+        let kid = header.kid.unwrap_or_default();
+        let cached = self
+            .jwks
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .as_ref()
+            .and_then(|jwks| jwks.find(&kid).cloned());
+        // The unknown key may be rotated, fetch the JWKS again.
+        let jwk = match cached {
+            Some(jwk) => jwk,
+            None => self
+                .fetch_jwks()
+                .await?
+                .find(&kid)
+                .cloned()
+                .ok_or_else(|| OidcError::InvalidToken("unknown key".to_owned()))?,
+        };
+        let key = DecodingKey::from_jwk(&jwk).map_err(invalid)?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&self.issuer]);
+        let token = jsonwebtoken::decode::<Claims>(id_token, &key, &validation).map_err(invalid)?;
+        Ok(token.claims)
+    }
+}
@@ -0,0 +1,169 @@
+//! Password hashing and verification, enabled by the `password` feature.
+//!
+//! The new hashes are argon2id PHC strings. The bcrypt and pbkdf2 hashes of the
+//! legacy systems are verified and reported as `Verified::NeedsRehash`.
+//! ```ignore
+//! let passwords = Passwords::new();
+//! let user = User::find_by_name(&form.name).await;
+//! match passwords.verify_user(&form.password, user.as_ref().map(|user| user.hash.as_str())) {
+//!     Verified::Valid => {}
+//!     Verified::NeedsRehash => user.save_hash(&passwords.hash(&form.password)?).await,
+//!     Verified::Invalid => return Ok(HttpResponse::Unauthorized().finish()),
+//! }
+//! ```
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use pbkdf2::Pbkdf2;
+use std::convert::TryFrom;
+
+pub use argon2::password_hash::Error as PasswordHashError;
+
+/// The result of the password verification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verified {
+    Invalid,
+    Valid,
+    /// Valid, but the hash is legacy or has the old parameters. Hash the password again and save it.
+    NeedsRehash,
+}
+
+impl Verified {
+    pub fn is_valid(&self) -> bool {
+        *self != Verified::Invalid
+    }
+}
+
+/// The argon2id parameters of the password hashes, the defaults follow OWASP:
+/// 19 MiB memory, 2 iterations and 1 degree of parallelism.
+#[derive(Clone, Debug)]
+pub struct Passwords {
+    memory_cost: u32,
+    time_cost: u32,
+    parallelism: u32,
+}
+
+impl Default for Passwords {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Passwords {
+    pub fn new() -> Self {
+        Self {
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    /// The memory size in KiB.
+    pub fn memory_cost(mut self, memory_cost: u32) -> Self {
+        self.memory_cost = memory_cost;
+        self
+    }
+
+    /// The number of iterations.
+    pub fn time_cost(mut self, time_cost: u32) -> Self {
+        self.time_cost = time_cost;
+        self
+    }
+
+    /// The degree of parallelism.
+    pub fn parallelism(mut self, parallelism: u32) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>, PasswordHashError> {
+        let params = Params::new(self.memory_cost, self.time_cost, self.parallelism, None)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// Hash the password with a random salt, into an argon2id PHC string.
+    pub fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = self.argon2()?.hash_password(password.as_bytes(), &salt)?;
+        Ok(hash.to_string())
+    }
+
+    /// Verify the password with the hash in constant time.
+    /// The argon2, pbkdf2 PHC strings and the bcrypt hashes are supported.
+    pub fn verify(&self, password: &str, hash: &str) -> Verified {
+        // bcrypt uses the modular crypt format instead of PHC.
+        if hash.starts_with("$2") {
+            return match bcrypt::verify(password, hash) {
+                Ok(true) => Verified::NeedsRehash,
+                _ => Verified::Invalid,
+            };
+        }
+        let parsed = match PasswordHash::new(hash) {
+            Ok(parsed) => parsed,
+            Err(_) => return Verified::Invalid,
+        };
+        let valid = match parsed.algorithm.as_str() {
+            "argon2id" | "argon2i" | "argon2d" => Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok(),
+            "pbkdf2" | "pbkdf2-sha256" | "pbkdf2-sha512" => {
+                Pbkdf2.verify_password(password.as_bytes(), &parsed).is_ok()
+            }
+            _ => false,
+        };
+        if !valid {
+            Verified::Invalid
+        } else if self.needs_rehash(&parsed) {
+            Verified::NeedsRehash
+        } else {
+            Verified::Valid
+        }
+    }
+
+    /// Verify the password of the user, `None` hash for the unknown user.
+    ///
+    /// The unknown user is verified with a dummy hash of the current parameters,
+    /// so that it takes the same time and the usernames can not be enumerated.
+    pub fn verify_user(&self, password: &str, hash: Option<&str>) -> Verified {
+        match hash {
+            Some(hash) => self.verify(password, hash),
+            None => {
+                let _ = self.verify(password, &self.dummy_hash());
+                Verified::Invalid
+            }
+        }
+    }
+
+    /// The PHC string of the current parameters that no password matches. It is
+    /// built without hashing, the verification costs the same as a real hash.
+    fn dummy_hash(&self) -> String {
+        format!(
+            "${}$v={}$m={},t={},p={}${}${}",
+            Algorithm::Argon2id.ident(),
+            u32::from(Version::V0x13),
+            self.memory_cost,
+            self.time_cost,
+            self.parallelism,
+            // 16 bytes of salt and 32 bytes of output in B64.
+            "A".repeat(22),
+            "A".repeat(43)
+        )
+    }
+
+    /// If the hash is not argon2id of the current parameters.
+    fn needs_rehash(&self, hash: &PasswordHash) -> bool {
+        if hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+        match Params::try_from(hash) {
+            Ok(params) => {
+                params.m_cost() != self.memory_cost
+                    || params.t_cost() != self.time_cost
+                    || params.p_cost() != self.parallelism
+            }
+            Err(_) => true,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use regex::Regex;
+
+/// The pattern to match the request path.
+/// ```ignore
+/// LoginManager::new(CookieSession::new(&[0; 32])).exclude(vec![
+///     PathPattern::prefix("/static"),
+///     PathPattern::glob("/**/*.ico"),
+///     PathPattern::regex("^/health(z)?$").unwrap(),
+/// ])
+/// ```
+#[derive(Clone, Debug)]
+pub enum PathPattern {
+    /// Match the path and its sub paths, `/static` matches `/static/a.css` but not `/statics`.
+    Prefix(String),
+    /// `*` matches in a path segment, `**` matches across segments and `?` matches a char.
+    /// `**/` matches zero or more segments, `/**/*.ico` matches `/favicon.ico`.
+    Glob(Regex),
+    Regex(Regex),
+}
+
+impl PathPattern {
+    pub fn prefix(prefix: &str) -> Self {
+        PathPattern::Prefix(prefix.to_owned())
+    }
+
+    pub fn glob(glob: &str) -> Self {
+        let mut re = String::from("^");
+        let mut chars = glob.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    // `/**/` also matches a single `/`, like in `/**/*.ico`.
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        re.push_str("(?:.*/)?");
+                    } else {
+                        re.push_str(".*");
+                    }
+                }
+                '*' => re.push_str("[^/]*"),
+                '?' => re.push_str("[^/]"),
+                c => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        re.push('$');
+        PathPattern::Glob(Regex::new(&re).expect("the escaped glob is a valid regex"))
+    }
+
+    pub fn regex(re: &str) -> Result<Self, regex::Error> {
+        Ok(PathPattern::Regex(Regex::new(re)?))
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        match self {
+            PathPattern::Prefix(prefix) => match path.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+                None => false,
+            },
+            PathPattern::Glob(re) | PathPattern::Regex(re) => re.is_match(path),
+        }
+    }
+}
+
+impl From<&str> for PathPattern {
+    fn from(prefix: &str) -> Self {
+        PathPattern::prefix(prefix)
+    }
+}
+
+impl From<Regex> for PathPattern {
+    fn from(re: Regex) -> Self {
+        PathPattern::Regex(re)
+    }
+}
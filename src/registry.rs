@@ -0,0 +1,416 @@
+use crate::loginmanager::{Config, LoginInfo};
+use actix_web::http::header::USER_AGENT;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, ResponseError};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// A session in the `SessionRegistry`.
+#[derive(Clone, Debug)]
+pub struct SessionRecord {
+    /// The session id, see `LoginInfo::session_id`.
+    pub id: String,
+    /// The serialized keys of the accounts the session logged in, see `Accounts`.
+    pub users: Vec<String>,
+    pub created: SystemTime,
+    pub last_seen: SystemTime,
+    /// The client ip of the login.
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// The server side record of the sessions, for the decoders like `CookieSession`
+/// which keep nothing on the server. The sessions not in the registry are logged out.
+///
+/// A session is registered for all the accounts it logged in.
+pub trait SessionRegistry {
+    /// The sessions of the user in the realm, the oldest first, any account of them. The sessions whose
+    /// cookie is gone never come back, so the stores expire the idle ones, see
+    /// `MemoryRegistry::idle_timeout`. The expired sessions are not returned by any method.
+    fn sessions(&self, realm: &str, user: &str) -> Vec<SessionRecord>;
+
+    fn get(&self, realm: &str, id: &str) -> Option<SessionRecord>;
+
+    /// Add the session, or replace the one of the same id.
+    fn insert(&self, realm: &str, session: SessionRecord);
+
+    fn remove(&self, realm: &str, id: &str) -> Option<SessionRecord>;
+
+    /// Move the session to the new id, at once so that the old id never works again.
+    /// Return false if the old id is not registered.
+    fn rotate(&self, realm: &str, old_id: &str, new_id: &str) -> bool;
+
+    /// Update the `last_seen` of the session, on every request of it. The stores
+    /// may skip the writes within a minute or so.
+    fn touch(&self, _realm: &str, _id: &str, _time: SystemTime) {}
+}
+
+type Sessions = HashMap<(String, String), SessionRecord>;
+
+/// The default idle timeout of `MemoryRegistry`.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The `SessionRegistry` in memory, for the apps of one process and the tests.
+/// The clones share the sessions, see `UserCache` for the app factory.
+/// ```ignore
+/// let registry = MemoryRegistry::new();
+/// HttpServer::new(move || {
+///     App::new().wrap(
+///         LoginManager::new(CookieSession::new(&[0; 32]))
+///             .session_registry(registry.clone())
+///             .max_sessions_per_user(3, SessionLimit::EvictOldest),
+///     )
+/// })
+/// ```
+#[derive(Clone)]
+pub struct MemoryRegistry {
+    /// The sessions by the realm and the id.
+    sessions: Arc<Mutex<Sessions>>,
+    idle_timeout: Duration,
+}
+
+impl Default for MemoryRegistry {
+    fn default() -> Self {
+        Self {
+            sessions: Arc::default(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+}
+
+impl MemoryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expire the sessions not seen for `timeout`, default `DEFAULT_IDLE_TIMEOUT`.
+    /// Set it to the max age of the session cookie, or longer.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn is_live(&self, session: &SessionRecord, now: SystemTime) -> bool {
+        now.duration_since(session.last_seen)
+            .map_or(true, |idle| idle < self.idle_timeout)
+    }
+}
+
+fn key(realm: &str, id: &str) -> (String, String) {
+    (realm.to_owned(), id.to_owned())
+}
+
+impl SessionRegistry for MemoryRegistry {
+    fn sessions(&self, realm: &str, user: &str) -> Vec<SessionRecord> {
+        let now = SystemTime::now();
+        let mut sessions = self
+            .lock()
+            .iter()
+            .filter(|((r, _), session)| r == realm && session.users.iter().any(|u| u == user))
+            .filter(|(_, session)| self.is_live(session, now))
+            .map(|(_, session)| session.clone())
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|session| session.created);
+        sessions
+    }
+
+    fn get(&self, realm: &str, id: &str) -> Option<SessionRecord> {
+        let session = self.lock().get(&key(realm, id)).cloned()?;
+        Some(session).filter(|session| self.is_live(session, SystemTime::now()))
+    }
+
+    fn insert(&self, realm: &str, session: SessionRecord) {
+        let now = SystemTime::now();
+        let mut sessions = self.lock();
+        // The expired sessions are dropped on login, so the map does not grow forever.
+        sessions.retain(|_, session| self.is_live(session, now));
+        sessions.insert(key(realm, &session.id), session);
+    }
+
+    fn remove(&self, realm: &str, id: &str) -> Option<SessionRecord> {
+        let session = self.lock().remove(&key(realm, id))?;
+        Some(session).filter(|session| self.is_live(session, SystemTime::now()))
+    }
+
+    fn rotate(&self, realm: &str, old_id: &str, new_id: &str) -> bool {
+        let now = SystemTime::now();
+        let mut sessions = self.lock();
+        match sessions.remove(&key(realm, old_id)) {
+            Some(mut session) if self.is_live(&session, now) => {
+                session.id = new_id.to_owned();
+                sessions.insert(key(realm, new_id), session);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn touch(&self, realm: &str, id: &str, time: SystemTime) {
+        if let Some(session) = self.lock().get_mut(&key(realm, id)) {
+            session.last_seen = time;
+        }
+    }
+}
+
+/// The browser and the operating system of the `User-Agent`, for the session list.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct UserAgent {
+    /// The name and the major version, like `Firefox 121`.
+    pub browser: Option<String>,
+    pub os: Option<String>,
+}
+
+impl UserAgent {
+    /// Parse the common browsers, the others are `None`.
+    pub fn parse(user_agent: &str) -> Self {
+        // The tokens in the order of precedence, Chrome also claims to be Safari.
+        const BROWSERS: [(&str, &str); 7] = [
+            ("Edg/", "Edge"),
+            ("OPR/", "Opera"),
+            ("FxiOS/", "Firefox"),
+            ("Firefox/", "Firefox"),
+            ("CriOS/", "Chrome"),
+            ("Chrome/", "Chrome"),
+            ("Version/", "Safari"),
+        ];
+        const SYSTEMS: [(&str, &str); 7] = [
+            ("Windows", "Windows"),
+            ("Android", "Android"),
+            ("iPhone", "iOS"),
+            ("iPad", "iOS"),
+            ("Mac OS X", "macOS"),
+            ("CrOS", "ChromeOS"),
+            ("Linux", "Linux"),
+        ];
+        let browser = BROWSERS.iter().find_map(|(token, name)| {
+            let version = &user_agent[user_agent.find(token)? + token.len()..];
+            let major = version
+                .split(|c: char| !c.is_ascii_digit())
+                .next()
+                .unwrap_or_default();
+            Some(match major {
+                "" => name.to_string(),
+                major => format!("{} {}", name, major),
+            })
+        });
+        let os = SYSTEMS
+            .iter()
+            .find(|(token, _)| user_agent.contains(token))
+            .map(|(_, name)| name.to_string());
+        Self { browser, os }
+    }
+}
+
+/// A session of the user, by `list_sessions`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ActiveSession {
+    /// The hash of the session id for `revoke_session`, the id itself is never shown.
+    pub handle: String,
+    pub created: SystemTime,
+    pub last_seen: SystemTime,
+    pub ip: Option<String>,
+    pub user_agent: UserAgent,
+    /// It is the session of the request.
+    pub current: bool,
+}
+
+fn handle(id: &str) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.input_str(id);
+    sha256.result_str()[..32].to_owned()
+}
+
+/// The client of the login request, read before the login info is borrowed.
+pub(crate) struct Device {
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl Device {
+    pub(crate) fn of(req: &HttpRequest) -> Self {
+        let ip = req
+            .connection_info()
+            .realip_remote_addr()
+            .map(str::to_owned);
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|agent| agent.to_str().ok())
+            .map(str::to_owned);
+        Self { ip, user_agent }
+    }
+}
+
+/// What to do when the user logs in with `max_sessions_per_user` sessions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionLimit {
+    /// Refuse the new login.
+    Reject,
+    /// Log out the oldest sessions.
+    EvictOldest,
+}
+
+/// The login is refused by `SessionLimit::Reject`, it is `403 Forbidden`.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionLimitExceeded {
+    pub limit: usize,
+}
+
+impl fmt::Display for SessionLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at most {} sessions are allowed", self.limit)
+    }
+}
+
+impl std::error::Error for SessionLimitExceeded {}
+
+impl ResponseError for SessionLimitExceeded {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+/// Apply the session limit before the user logs in by the request of the login info,
+/// return the number of the evicted sessions.
+pub(crate) fn admit(info: &LoginInfo, user: &str) -> Result<usize, SessionLimitExceeded> {
+    let config = &info.config;
+    let (registry, (limit, policy)) = match (&config.registry, config.session_limit) {
+        (Some(registry), Some(session_limit)) => (registry, session_limit),
+        _ => return Ok(0),
+    };
+    // The session of the request is replaced by the login.
+    let sessions = registry
+        .sessions(&config.realm, user)
+        .into_iter()
+        .filter(|session| Some(&session.id) != info.session_id.as_ref())
+        .collect::<Vec<_>>();
+    let excess = (sessions.len() + 1).saturating_sub(limit);
+    if excess == 0 {
+        return Ok(0);
+    }
+    if policy == SessionLimit::Reject {
+        return Err(SessionLimitExceeded { limit });
+    }
+    // The limit is at least 1, so the excess are all the sessions at most.
+    for session in &sessions[..excess] {
+        registry.remove(&config.realm, &session.id);
+    }
+    Ok(excess)
+}
+
+/// Register the session of the login info for all its accounts. A registered session
+/// keeps its time and device, unregister it before to start a new one.
+pub(crate) fn register(info: &LoginInfo, device: Device) {
+    let config = &info.config;
+    let (registry, id) = match (&config.registry, &info.session_id) {
+        (Some(registry), Some(id)) if !info.accounts.is_empty() => (registry, id),
+        _ => return,
+    };
+    let now = SystemTime::now();
+    let session = match registry.get(&config.realm, id) {
+        Some(session) => SessionRecord {
+            users: info.accounts.clone(),
+            ..session
+        },
+        None => SessionRecord {
+            id: id.clone(),
+            users: info.accounts.clone(),
+            created: now,
+            last_seen: now,
+            ip: device.ip,
+            user_agent: device.user_agent,
+        },
+    };
+    registry.insert(&config.realm, session);
+}
+
+pub(crate) fn unregister(info: &LoginInfo) {
+    let config = &info.config;
+    if let (Some(registry), Some(id)) = (&config.registry, &info.session_id) {
+        registry.remove(&config.realm, id);
+    }
+}
+
+pub(crate) fn rotate(config: &Config, old_id: &str, new_id: &str) {
+    if let Some(registry) = &config.registry {
+        registry.rotate(&config.realm, old_id, new_id);
+    }
+}
+
+/// If the session of the decoded login info is still registered, it is seen now.
+pub(crate) fn check(info: &LoginInfo) -> bool {
+    let config = &info.config;
+    match (&config.registry, &info.session_id) {
+        (None, _) => true,
+        (Some(registry), Some(id)) => {
+            let registered = registry.get(&config.realm, id).is_some();
+            if registered {
+                registry.touch(&config.realm, id, SystemTime::now());
+            }
+            registered
+        }
+        (Some(_), None) => false,
+    }
+}
+
+/// The real user of the login info, the first impersonator while impersonating.
+fn owner(info: &LoginInfo) -> Option<&String> {
+    info.impersonators.first().or(info.key_str.as_ref())
+}
+
+/// The sessions of the real user of the login info.
+pub(crate) fn list(info: &LoginInfo) -> Vec<ActiveSession> {
+    let config = &info.config;
+    let (registry, user) = match (&config.registry, owner(info)) {
+        (Some(registry), Some(user)) => (registry, user),
+        _ => return Vec::new(),
+    };
+    registry
+        .sessions(&config.realm, user)
+        .into_iter()
+        .map(|session| ActiveSession {
+            handle: handle(&session.id),
+            created: session.created,
+            last_seen: session.last_seen,
+            ip: session.ip,
+            user_agent: session
+                .user_agent
+                .as_deref()
+                .map(UserAgent::parse)
+                .unwrap_or_default(),
+            current: Some(&session.id) == info.session_id.as_ref(),
+        })
+        .collect()
+}
+
+/// Remove the sessions of the real user that `f` selects, return the removed ids.
+pub(crate) fn revoke<F>(info: &LoginInfo, f: F) -> Vec<String>
+where
+    F: Fn(&SessionRecord) -> bool,
+{
+    let config = &info.config;
+    let (registry, user) = match (&config.registry, owner(info)) {
+        (Some(registry), Some(user)) => (registry, user),
+        _ => return Vec::new(),
+    };
+    registry
+        .sessions(&config.realm, user)
+        .into_iter()
+        .filter(|session| f(session))
+        .filter_map(|session| registry.remove(&config.realm, &session.id))
+        .map(|session| session.id)
+        .collect()
+}
+
+pub(crate) fn is_handle(id: &str, handle_str: &str) -> bool {
+    handle(id) == handle_str
+}
@@ -0,0 +1,98 @@
+use crate::user::{UserMinix, UserWrapAuth};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, FromRequest};
+use futures::{
+    future::{ok, Ready},
+    Future,
+};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::rc::Rc;
+
+/// The middleware requires an actived and authenticated user for all the wrapped services,
+/// like `UserWrapAuth<U>` in every handler.
+///
+/// The user is loaded before calling the service and cached for `UserWrap<U>`.
+/// The unauthorized requests are rejected with `401 Unauthorized`, which the `LoginManager`
+/// of the app turns into a redirect.
+/// ```ignore
+/// App::new()
+///     .wrap(LoginManager::new(CookieSession::new(&[0; 32])))
+///     .service(
+///         web::scope("/admin")
+///             .wrap(RequireLogin::<User>::new())
+///             .service(Files::new("/static", "./admin")),
+///     )
+///     .service(
+///         web::resource("/profile")
+///             .wrap(RequireLogin::<User>::new())
+///             .route(web::get().to(profile)),
+///     )
+/// ```
+pub struct RequireLogin<U> {
+    _user: PhantomData<U>,
+}
+
+impl<U> RequireLogin<U> {
+    pub fn new() -> Self {
+        Self { _user: PhantomData }
+    }
+}
+
+impl<U> Default for RequireLogin<U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, B, U: 'static> Transform<S, ServiceRequest> for RequireLogin<U>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    U: UserMinix,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireLoginMiddleware<S, U>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequireLoginMiddleware {
+            service: Rc::new(service),
+            _user: PhantomData,
+        })
+    }
+}
+
+pub struct RequireLoginMiddleware<S, U> {
+    service: Rc<S>,
+    _user: PhantomData<U>,
+}
+
+impl<S, B, U: 'static> Service<ServiceRequest> for RequireLoginMiddleware<S, U>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    U: UserMinix,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + 'static>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let user = UserWrapAuth::<U>::from_request(req.request(), &mut Payload::None);
+        Box::pin(async move {
+            match user.await {
+                Ok(_) => Ok(service.call(req).await?.map_into_left_body()),
+                Err(err) => Ok(req.error_response(err).map_into_right_body()),
+            }
+        })
+    }
+}
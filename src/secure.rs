@@ -0,0 +1,8 @@
+/// Compare the strings in constant time, for the codes and the tokens of the requests.
+pub(crate) fn secure_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
@@ -0,0 +1,136 @@
+//! Helpers to test the handlers behind loginmanager, enabled by the `test-utils` feature.
+//!
+//! ```ignore
+//! use actix_loginmanager::test::{assert_logged_in, TestRequestExt};
+//!
+//! #[actix_web::test]
+//! async fn test_index() {
+//!     let session = || CookieSession::new(&[0; 32]).secure(false);
+//!     let app = test::init_service(
+//!         App::new().wrap(LoginManager::new(session())).service(index).service(login),
+//!     )
+//!     .await;
+//!     let req = TestRequest::get().uri("/").login_as(&session(), &tom).to_request();
+//!     assert_eq!(test::call_service(&app, req).await.status(), 200);
+//!
+//!     let req = TestRequest::post().uri("/login").set_form(&form).to_request();
+//!     assert_logged_in(&session(), &test::call_service(&app, req).await, &tom);
+//! }
+//! ```
+use crate::cooke_session::{fingerprint, CookieSession, Session};
+use crate::loginmanager::{new_session_id, LoginInfos, LoginState};
+use crate::user::{UserMinix, UserWrap};
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::test::TestRequest;
+use actix_web::{HttpMessage, HttpRequest};
+
+/// Build the session cookie that logs in the user, for the requests without
+/// the client ip and the user agent like `TestRequest::default()`.
+pub fn login_cookie<U: UserMinix>(session: &CookieSession, user: &U) -> Cookie<'static> {
+    login_cookie_for(session, user, None, None)
+}
+
+/// Build the session cookie that logs in the user, for the requests with
+/// the client ip and the user agent.
+pub fn login_cookie_for<U: UserMinix>(
+    session: &CookieSession,
+    user: &U,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+) -> Cookie<'static> {
+    let key_str = serde_json::to_string(user.get_id()).expect("the user key can not serialize");
+    session.session_cookie(&Session {
+        id: new_session_id(),
+        fingerprint: fingerprint(ip, user_agent),
+        user_id: Some(key_str.clone()),
+        accounts: vec![key_str],
+        impersonators: Vec::new(),
+        pending: false,
+    })
+    .expect("the session can not be written")
+}
+
+/// Log in the `TestRequest`.
+pub trait TestRequestExt {
+    /// Add the session cookie that logs in the user, see `login_cookie`.
+    fn login_as<U: UserMinix>(self, session: &CookieSession, user: &U) -> Self;
+}
+
+impl TestRequestExt for TestRequest {
+    fn login_as<U: UserMinix>(self, session: &CookieSession, user: &U) -> Self {
+        self.cookie(login_cookie(session, user))
+    }
+}
+
+/// Inject the user key into the request, the user extractors load the user by it.
+///
+/// LoginManager keeps the injected key instead of decoding the request.
+pub fn inject_key<U: UserMinix + 'static>(req: &HttpRequest, key: &U::Key) {
+    let key_str = serde_json::to_string(key).ok();
+    LoginInfos::update(req, U::REALM, |info| {
+        info.accounts = key_str.iter().cloned().collect();
+        info.key_str = key_str;
+        info.pending = false;
+        info.state = LoginState::Wait;
+    });
+}
+
+/// Inject the logged in user into the request, the user extractors return it
+/// without `UserMinix::get_user`.
+/// ```ignore
+/// let req = TestRequest::default().to_http_request();
+/// inject_user(&req, tom);
+/// let resp = index(UserWrap::extract(&req).await.unwrap()).await;
+/// ```
+pub fn inject_user<U: UserMinix + 'static>(req: &HttpRequest, user: U) {
+    inject_key::<U>(req, user.get_id());
+    req.extensions_mut().insert(UserWrap::from(user));
+}
+
+fn response_session<B>(session: &CookieSession, res: &ServiceResponse<B>) -> Option<Session> {
+    let cookie = res
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == session.cookie_name())?;
+    session.read_cookie(&cookie)
+}
+
+/// The key of the user the response logs in, by decoding its `Set-Cookie` header.
+pub fn logged_in_key<B>(session: &CookieSession, res: &ServiceResponse<B>) -> Option<String> {
+    response_session(session, res)?.user_id
+}
+
+/// The id of the session the response writes, to check that it is renewed.
+pub fn session_id<B>(session: &CookieSession, res: &ServiceResponse<B>) -> Option<String> {
+    Some(response_session(session, res)?.id)
+}
+
+/// Assert the response logs in the user.
+pub fn assert_logged_in<U: UserMinix, B>(
+    session: &CookieSession,
+    res: &ServiceResponse<B>,
+    user: &U,
+) {
+    let key_str = serde_json::to_string(user.get_id()).expect("the user key can not serialize");
+    assert_eq!(
+        logged_in_key(session, res).as_deref(),
+        Some(key_str.as_str()),
+        "the response does not log in the user {}",
+        key_str
+    );
+}
+
+/// Assert the response logs out, by removing the session cookie or the user key in it.
+pub fn assert_logged_out<B>(session: &CookieSession, res: &ServiceResponse<B>) {
+    let cookie = res
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == session.cookie_name())
+        .expect("the response does not set the session cookie");
+    let logged_out = cookie.value().is_empty()
+        || session
+            .read_cookie(&cookie)
+            .is_none_or(|session| session.user_id.is_none());
+    assert!(logged_out, "the response does not log out");
+}
@@ -0,0 +1,190 @@
+//! Time-based one-time passwords of RFC 6238, enabled by the `totp` feature.
+//!
+//! ```ignore
+//! // enrollment
+//! let secret = totp::generate_secret();
+//! let uri = Totp::new().provisioning_uri(&secret, "Example", &user.email);
+//! // show the uri as a QR code, and save the secret after the user verified a code.
+//!
+//! // verification, the second factor of `login_partial`
+//! match Totp::new().verify_user(&user, &form.code) {
+//!     Some(step) => {
+//!         user.save_totp_last_step(step).await;
+//!         loginmanager::complete_login::<User>(&req);
+//!     }
+//!     None => return HttpResponse::Unauthorized().finish(),
+//! }
+//! ```
+use crate::secure::secure_eq;
+use crate::user::UserMinix;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The user with the totp secret, extending `UserMinix`.
+pub trait TotpUser: UserMinix {
+    /// The base32 secret, `None` if the user has not enrolled.
+    fn totp_secret(&self) -> Option<&str>;
+
+    /// The last step passed the verification, the codes of it and before are rejected.
+    fn totp_last_step(&self) -> Option<u64>;
+}
+
+/// Generate a random base32 secret of 160 bits.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decode the base32 secret, the case, spaces and paddings are ignored.
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let c = c.to_ascii_uppercase() as u8;
+        let value = BASE32_ALPHABET.iter().position(|&a| a == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// The settings of totp, the defaults are 6 digits, 30 seconds step and 1 step skew,
+/// which the authenticator apps support.
+#[derive(Clone, Debug)]
+pub struct Totp {
+    digits: u32,
+    step: u64,
+    skew: u64,
+}
+
+impl Default for Totp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Totp {
+    pub fn new() -> Self {
+        Self {
+            digits: 6,
+            step: 30,
+            skew: 1,
+        }
+    }
+
+    /// The length of the codes, clamped to 6..=8 which RFC 4226 and the apps support.
+    pub fn digits(mut self, digits: u32) -> Self {
+        self.digits = digits.clamp(6, 8);
+        self
+    }
+
+    /// The seconds of a time step.
+    pub fn step(mut self, step: u64) -> Self {
+        self.step = step.max(1);
+        self
+    }
+
+    /// The steps before and after the current one also accepted, for the clock drift.
+    /// It is clamped to 10, every step is one more code to guess.
+    pub fn skew(mut self, skew: u64) -> Self {
+        self.skew = skew.min(10);
+        self
+    }
+
+    /// The `otpauth://` uri for the authenticator apps, usually shown as a QR code.
+    pub fn provisioning_uri(&self, secret: &str, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            urlencoding::encode(issuer),
+            urlencoding::encode(account),
+            secret,
+            urlencoding::encode(issuer),
+            self.digits,
+            self.step
+        )
+    }
+
+    /// The code of the time step, `None` if the secret is not base32.
+    pub fn code(&self, secret: &str, step: u64) -> Option<String> {
+        let key = base32_decode(secret)?;
+        let mut hmac = Hmac::new(Sha1::new(), &key);
+        hmac.input(&step.to_be_bytes());
+        let hash = hmac.result();
+        let hash = hash.code();
+        let offset = (hash[hash.len() - 1] & 0xf) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        let code = binary as u64 % 10u64.pow(self.digits);
+        Some(format!("{:0width$}", code, width = self.digits as usize))
+    }
+
+    /// The time step of the unix time.
+    pub fn step_at(&self, time: u64) -> u64 {
+        time / self.step
+    }
+
+    /// Verify the code at the unix time, return the step it matched.
+    /// The steps not after `last_step` are rejected, so a code can be used only once.
+    pub fn verify_at(
+        &self,
+        secret: &str,
+        code: &str,
+        last_step: Option<u64>,
+        time: u64,
+    ) -> Option<u64> {
+        let current = self.step_at(time);
+        let first = current.saturating_sub(self.skew);
+        (first..=current.saturating_add(self.skew))
+            .filter(|step| last_step.is_none_or(|last_step| *step > last_step))
+            .find(|step| {
+                self.code(secret, *step)
+                    .is_some_and(|expected| secure_eq(&expected, code.trim()))
+            })
+    }
+
+    /// Verify the code now, see `verify_at`.
+    pub fn verify(&self, secret: &str, code: &str, last_step: Option<u64>) -> Option<u64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        self.verify_at(secret, code, last_step, now)
+    }
+
+    /// Verify the code of the user, return the step to save as the user's last step.
+    pub fn verify_user<U: TotpUser>(&self, user: &U, code: &str) -> Option<u64> {
+        self.verify(user.totp_secret()?, code, user.totp_last_step())
+    }
+}
@@ -0,0 +1,199 @@
+use actix_web::{
+    http::{
+        header::{self, Header, HeaderValue, LOCATION},
+        StatusCode,
+    },
+    HttpRequest, HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use std::fmt;
+use std::rc::Rc;
+
+/// The reason why a request is unauthorized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// No user logged in, or the user can not be loaded.
+    Unauthenticated,
+    /// The user is not actived or not authenticated.
+    Inactive,
+    /// The user logged in by `login_partial`, the second factor is pending.
+    MfaPending,
+}
+
+impl Reason {
+    fn detail(&self) -> &'static str {
+        match self {
+            Reason::Unauthenticated => "No authentication.",
+            Reason::Inactive => "The user is not active.",
+            Reason::MfaPending => "The second factor is required.",
+        }
+    }
+}
+
+/// The error returned by the user extractors, `401 Unauthorized`.
+///
+/// LoginManager reads the reason from it and passes to the `UnauthorizedHandler`.
+#[derive(Debug)]
+pub struct Unauthorized {
+    reason: Reason,
+    realm: Option<String>,
+}
+
+impl Unauthorized {
+    pub fn new(reason: Reason) -> Self {
+        Self {
+            reason,
+            realm: None,
+        }
+    }
+
+    /// Only the LoginManager of the realm will handle it.
+    pub fn in_realm(mut self, realm: &str) -> Self {
+        self.realm = Some(realm.to_owned());
+        self
+    }
+
+    pub fn reason(&self) -> Reason {
+        self.reason
+    }
+
+    pub fn realm(&self) -> Option<&str> {
+        self.realm.as_deref()
+    }
+}
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.reason.detail())
+    }
+}
+
+impl ResponseError for Unauthorized {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNAUTHORIZED
+    }
+}
+
+type CustomHandler = Rc<dyn Fn(&HttpRequest, Reason, &str) -> HttpResponse>;
+
+/// How LoginManager responds to the unauthorized requests.
+///
+/// It can be overridden for a scope by registering it as app data.
+/// ```ignore
+/// App::new()
+///     .wrap(LoginManager::new(CookieSession::new(&[0; 32])))
+///     .service(
+///         web::scope("/api")
+///             .app_data(UnauthorizedHandler::Problem)
+///             .service(api),
+///     )
+/// ```
+#[derive(Clone)]
+pub enum UnauthorizedHandler {
+    /// Keep the `401 Unauthorized` response.
+    Status,
+    /// `302 Found` to the login view.
+    Redirect,
+    /// `401 Unauthorized` with a JSON problem details body (RFC 7807).
+    Problem,
+    /// `401 Unauthorized` with the `HX-Redirect` header to the login view, for htmx and XHR.
+    HxRedirect,
+    /// Choose from `HxRedirect`, `Problem` and `Redirect` by the request headers. Default.
+    Negotiate,
+    /// Build the response from the request, the reason and the login url.
+    Custom(CustomHandler),
+}
+
+impl UnauthorizedHandler {
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&HttpRequest, Reason, &str) -> HttpResponse + 'static,
+    {
+        UnauthorizedHandler::Custom(Rc::new(f))
+    }
+
+    /// Return `None` if the `401 Unauthorized` response should be kept.
+    pub(crate) fn respond(
+        &self,
+        req: &HttpRequest,
+        reason: Reason,
+        login_url: &str,
+    ) -> Option<HttpResponse> {
+        match self {
+            UnauthorizedHandler::Status => None,
+            UnauthorizedHandler::Redirect => Some(
+                HttpResponse::Found()
+                    .insert_header((LOCATION, login_url))
+                    .finish(),
+            ),
+            UnauthorizedHandler::Problem => Some(
+                HttpResponse::Unauthorized()
+                    .content_type("application/problem+json")
+                    .json(Problem {
+                        type_: "about:blank",
+                        title: "Unauthorized",
+                        status: StatusCode::UNAUTHORIZED.as_u16(),
+                        detail: reason.detail(),
+                        instance: req.path(),
+                        reason,
+                    }),
+            ),
+            UnauthorizedHandler::HxRedirect => Some(
+                HttpResponse::Unauthorized()
+                    .insert_header(("HX-Redirect", login_url))
+                    .finish(),
+            ),
+            UnauthorizedHandler::Negotiate => {
+                let handler = if is_xhr(req) {
+                    UnauthorizedHandler::HxRedirect
+                } else if accepts_json(req) {
+                    UnauthorizedHandler::Problem
+                } else {
+                    UnauthorizedHandler::Redirect
+                };
+                handler.respond(req, reason, login_url)
+            }
+            UnauthorizedHandler::Custom(f) => Some(f(req, reason, login_url)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: &'static str,
+    instance: &'a str,
+    reason: Reason,
+}
+
+fn header_str<'a>(req: &'a HttpRequest, name: &str) -> &'a str {
+    req.headers()
+        .get(name)
+        .and_then(|value: &HeaderValue| value.to_str().ok())
+        .unwrap_or("")
+}
+
+pub(crate) fn is_xhr(req: &HttpRequest) -> bool {
+    header_str(req, "HX-Request") == "true"
+        || header_str(req, "X-Requested-With").eq_ignore_ascii_case("XMLHttpRequest")
+}
+
+/// Whether the request prefers JSON to HTML, a missing `Accept` prefers HTML.
+pub(crate) fn accepts_json(req: &HttpRequest) -> bool {
+    let accept = match header::Accept::parse(req) {
+        Ok(accept) => accept.ranked(),
+        Err(_) => return false,
+    };
+    for mime in accept {
+        match (mime.type_().as_str(), mime.subtype().as_str()) {
+            ("text", "html") | ("application", "xhtml+xml") => return false,
+            ("application", "json") | ("application", "problem+json") => return true,
+            _ => {}
+        }
+    }
+    false
+}
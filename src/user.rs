@@ -1,15 +1,16 @@
-use crate::loginmanager::LoginInfo;
-use actix_web::{
-    dev::Payload, error::InternalError, http::StatusCode, Error, FromRequest, HttpMessage,
-    HttpRequest,
-};
+use crate::cache::{SharedUser, UserCache};
+use crate::error::LoginManagerError;
+use crate::loginmanager::{LoginInfos, DEFAULT_REALM};
+use crate::unauthorized::{Reason, Unauthorized};
+use actix_web::{dev::Payload, Error, FromRequest, HttpMessage, HttpRequest};
 use futures::Future;
 use serde::{de::DeserializeOwned, Serialize};
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
 /// the base user trait
 /// ### Example: Get user from database
-/// ```rust
+/// ```ignore
 /// # use actix_web::{HttpRequest,Data};
 /// # use futures::Future;
 /// # use std::pin::Pin;
@@ -40,13 +41,16 @@ use std::rc::Rc;
 /// }
 /// ```
 pub trait UserMinix: Sized {
-    ///
+    /// The future returned by `get_user`.
     type Future: Future<Output = Option<Self>>;
 
     /// The type of User, must be same as Loginmanager.
     /// Otherwise no user will be returned.
     type Key: Serialize + DeserializeOwned;
 
+    /// The realm of the LoginManager that logs in the user, default `DEFAULT_REALM`.
+    const REALM: &'static str = DEFAULT_REALM;
+
     /// Get user from id and req,Tip:can use req.app_data to obtain
     /// database connection defined in Web app.
     fn get_user(id: &Self::Key, req: &HttpRequest) -> Self::Future;
@@ -63,6 +67,26 @@ pub trait UserMinix: Sized {
     fn is_actived(&self) -> bool {
         true
     }
+
+    /// Share the user with the user cache of LoginManager, default `None` not to cache it.
+    /// ```ignore
+    /// fn to_shared(&self) -> Option<SharedUser> {
+    ///     Some(Arc::new(self.clone()))
+    /// }
+    /// ```
+    fn to_shared(&self) -> Option<SharedUser> {
+        None
+    }
+
+    /// Get the user back from the user cache of LoginManager.
+    /// ```ignore
+    /// fn from_shared(user: &SharedUser) -> Option<Self> {
+    ///     user.downcast_ref::<Self>().cloned()
+    /// }
+    /// ```
+    fn from_shared(_user: &SharedUser) -> Option<Self> {
+        None
+    }
 }
 
 /// The wrap of user Instance. It implements `FromRequest` trait.  
@@ -70,7 +94,7 @@ pub trait UserMinix: Sized {
 /// It will return `401 Unauthorized` if no key or error key.  
 ///
 /// If loginmanager set redirect true,then will rediret login_view.
-/// ```rust
+/// ```ignore
 /// #[get("/index")]
 /// async fn index(UserWrap(user): UserWrap<User>) -> impl Responder{
 ///     todo()!
@@ -120,33 +144,73 @@ where
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
         let req_clone: HttpRequest = req.clone();
         Box::pin(async move {
-            let extensions = &mut req_clone.extensions_mut();
-            if let Some(user) = extensions.get::<Self>() {
+            if let Some(user) = req_clone.extensions().get::<Self>() {
                 return Ok(user.clone());
-            } else {
-                match extensions.get::<LoginInfo>() {
-                    Some(LoginInfo {
-                        key_str: Some(key_str),
-                        ..
-                    }) => match serde_json::from_str::<T::Key>(&key_str) {
-                        Ok(key) => {
-                            let real_user = T::get_user(&key, &req_clone).await;
-                            if let Some(real_user) = real_user {
-                                let user = UserWrap(Rc::new(real_user));
-                                extensions.insert(user.clone());
-                                return Ok(user);
-                            }
-                        }
-                        _ => {}
-                    },
-                    _ => {}
-                };
+            }
+            let (key_str, cache) = user_key::<T>(&req_clone)?;
+            let cached = cache
+                .as_ref()
+                .and_then(|cache| cache.get::<T>(&key_str))
+                .and_then(|shared| T::from_shared(&shared));
+            let real_user = match cached {
+                Some(real_user) => real_user,
+                None => load_user::<T>(&req_clone, &key_str, cache.as_ref())
+                    .await
+                    .ok_or_else(|| unauthorized::<T>(Reason::Unauthenticated))?,
             };
-            return Err(InternalError::new("No authentication.", StatusCode::UNAUTHORIZED).into());
+            let user = UserWrap(Rc::new(real_user));
+            req_clone.extensions_mut().insert(user.clone());
+            Ok(user)
         })
     }
 }
 
+/// The key of the active account and the user cache, in the realm of `T`.
+/// The pending user of `login_partial` is not logged in yet.
+fn user_key<T: UserMinix>(req: &HttpRequest) -> Result<(String, Option<UserCache>), Error> {
+    let (key_str, cache, pending) = LoginInfos::get(req, T::REALM, |info| {
+        (info.key_str.clone(), info.config.cache.clone(), info.pending)
+    })
+    .ok_or_else(|| {
+        let err = LoginManagerError::MissingMiddleware(T::REALM.to_owned());
+        log::error!("{} for {}", err, req.path());
+        err
+    })?;
+    if pending {
+        return Err(unauthorized::<T>(Reason::MfaPending));
+    }
+    Ok((key_str.unwrap_or_default(), cache))
+}
+
+/// Load the user by `get_user` and share it with the user cache.
+async fn load_user<T: UserMinix + 'static>(
+    req: &HttpRequest,
+    key_str: &str,
+    cache: Option<&UserCache>,
+) -> Option<T> {
+    let key = serde_json::from_str::<T::Key>(key_str).ok()?;
+    let user = T::get_user(&key, req).await?;
+    if let (Some(cache), Some(shared)) = (cache, user.to_shared()) {
+        cache.insert::<T>(key_str, shared);
+    }
+    Some(user)
+}
+
+fn unauthorized<T: UserMinix>(reason: Reason) -> Error {
+    Unauthorized::new(reason).in_realm(T::REALM).into()
+}
+
+/// Check if the user is actived and authenticated.
+fn check_user<U: UserMinix>(user: &U) -> Result<(), Error> {
+    if !user.is_authenticated() {
+        Err(unauthorized::<U>(Reason::Unauthenticated))
+    } else if !user.is_actived() {
+        Err(unauthorized::<U>(Reason::Inactive))
+    } else {
+        Ok(())
+    }
+}
+
 /// The wrap of userwrap Instance. It will check if the user is actived and authenticated
 pub struct UserWrapAuth<U>(pub UserWrap<U>);
 
@@ -174,15 +238,336 @@ where
         let userwrap_future = UserWrap::from_request(req, pl);
         Box::pin(async move {
             let userwrap = userwrap_future.await?;
-            let userwrapauth = Self(userwrap);
-            let user = userwrapauth.as_ref();
-            if user.is_actived() && user.is_authenticated() {
-                return Ok(userwrapauth);
-            } else {
-                return Err(
-                    InternalError::new("No authentication.", StatusCode::UNAUTHORIZED).into(),
-                );
+            check_user(userwrap.user())?;
+            Ok(Self(userwrap))
+        })
+    }
+}
+
+/// The wrap of user Instance like `UserWrap`, but the user is in an `Arc`
+/// so it can be sent to the other threads, like `web::block` and `tokio::spawn`.
+/// ```ignore
+/// #[get("/report")]
+/// async fn report(SyncUserWrap(user): SyncUserWrap<User>) -> impl Responder {
+///     web::block(move || build_report(&user)).await
+/// }
+/// ```
+pub struct SyncUserWrap<T>(pub Arc<T>);
+
+impl<T> Clone for SyncUserWrap<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: 'static> SyncUserWrap<T>
+where
+    T: UserMinix + Send + Sync,
+{
+    pub fn new(user: T) -> Self {
+        Self(Arc::new(user))
+    }
+
+    pub fn user(&self) -> &T {
+        self.0.as_ref()
+    }
+}
+
+impl<U> From<U> for SyncUserWrap<U> {
+    fn from(u: U) -> Self {
+        SyncUserWrap(Arc::new(u))
+    }
+}
+
+impl<U> AsRef<U> for SyncUserWrap<U> {
+    fn as_ref(&self) -> &U {
+        self.0.as_ref()
+    }
+}
+
+impl<T: 'static> FromRequest for SyncUserWrap<T>
+where
+    T: UserMinix + Send + Sync,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req_clone: HttpRequest = req.clone();
+        Box::pin(async move {
+            if let Some(user) = req_clone.extensions().get::<Self>() {
+                return Ok(user.clone());
             }
+            let (key_str, cache) = user_key::<T>(&req_clone)?;
+            // The cached user is usually an `Arc<T>`, which is used without cloning.
+            let cached = cache
+                .as_ref()
+                .and_then(|cache| cache.get::<T>(&key_str))
+                .and_then(|shared| match shared.clone().downcast::<T>() {
+                    Ok(user) => Some(user),
+                    Err(_) => T::from_shared(&shared).map(Arc::new),
+                });
+            let real_user = match cached {
+                Some(real_user) => real_user,
+                None => load_user::<T>(&req_clone, &key_str, cache.as_ref())
+                    .await
+                    .map(Arc::new)
+                    .ok_or_else(|| unauthorized::<T>(Reason::Unauthenticated))?,
+            };
+            let user = SyncUserWrap(real_user);
+            req_clone.extensions_mut().insert(user.clone());
+            Ok(user)
+        })
+    }
+}
+
+/// The wrap of syncuserwrap Instance. It will check if the user is actived and authenticated
+pub struct SyncUserWrapAuth<U>(pub SyncUserWrap<U>);
+
+impl<U> From<U> for SyncUserWrapAuth<U> {
+    fn from(u: U) -> Self {
+        SyncUserWrapAuth(SyncUserWrap(Arc::new(u)))
+    }
+}
+
+impl<U> AsRef<U> for SyncUserWrapAuth<U> {
+    fn as_ref(&self) -> &U {
+        self.0 .0.as_ref()
+    }
+}
+
+impl<U: 'static> FromRequest for SyncUserWrapAuth<U>
+where
+    U: UserMinix + Send + Sync,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
+        let userwrap_future = SyncUserWrap::from_request(req, pl);
+        Box::pin(async move {
+            let userwrap = userwrap_future.await?;
+            check_user(userwrap.user())?;
+            Ok(Self(userwrap))
+        })
+    }
+}
+
+/// The user who is not logged in, like the `AnonymousUserMixin` of flask-login.
+/// ### Example
+/// ```ignore
+/// struct Guest {
+///     lang: String,
+/// }
+///
+/// impl AnonymousUser for Guest {
+///     fn anonymous(req: &HttpRequest) -> Self {
+///         todo!() // read the language from the request
+///     }
+/// }
+/// ```
+pub trait AnonymousUser: Sized {
+    /// Create the anonymous user of the request.
+    fn anonymous(req: &HttpRequest) -> Self;
+
+    /// return false, the anonymous user is never authenticated.
+    fn is_authenticated(&self) -> bool {
+        false
+    }
+
+    /// return false, the anonymous user is never actived.
+    fn is_actived(&self) -> bool {
+        false
+    }
+}
+
+/// The default anonymous user.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Anonymous;
+
+impl AnonymousUser for Anonymous {
+    fn anonymous(_: &HttpRequest) -> Self {
+        Anonymous
+    }
+}
+
+/// The user of the request, logged in or not. It implements `FromRequest` trait.
+///
+/// It never returns `401 Unauthorized`, so loginmanager will not redirect.
+/// ```ignore
+/// #[get("/")]
+/// async fn index(user: CurrentUser<User>) -> impl Responder {
+///     match user {
+///         CurrentUser::Authenticated(UserWrap(user)) => format!("hello {}", user.name),
+///         CurrentUser::Anonymous(_) => "hello guest".to_owned(),
+///     }
+/// }
+/// ```
+pub enum CurrentUser<U, A = Anonymous> {
+    Authenticated(UserWrap<U>),
+    Anonymous(A),
+}
+
+impl<U, A> CurrentUser<U, A>
+where
+    U: UserMinix,
+    A: AnonymousUser,
+{
+    /// return the user if logged in.
+    pub fn user(&self) -> Option<&U> {
+        match self {
+            CurrentUser::Authenticated(user) => Some(user.as_ref()),
+            CurrentUser::Anonymous(_) => None,
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        match self {
+            CurrentUser::Authenticated(user) => user.as_ref().is_authenticated(),
+            CurrentUser::Anonymous(anonymous) => anonymous.is_authenticated(),
+        }
+    }
+
+    pub fn is_actived(&self) -> bool {
+        match self {
+            CurrentUser::Authenticated(user) => user.as_ref().is_actived(),
+            CurrentUser::Anonymous(anonymous) => anonymous.is_actived(),
+        }
+    }
+}
+
+impl<U: 'static, A: 'static> FromRequest for CurrentUser<U, A>
+where
+    U: UserMinix,
+    A: AnonymousUser,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
+        let userwrap_future = UserWrap::from_request(req, pl);
+        let req = req.clone();
+        Box::pin(async move {
+            match userwrap_future.await {
+                Ok(user) => Ok(CurrentUser::Authenticated(user)),
+                // A broken setup is not an anonymous user.
+                Err(err) if err.as_error::<LoginManagerError>().is_some() => Err(err),
+                Err(_) => Ok(CurrentUser::Anonymous(A::anonymous(&req))),
+            }
+        })
+    }
+}
+
+/// A logged in account of `Accounts<U>`.
+pub struct Account<U> {
+    /// The index for `switch_account`.
+    pub index: usize,
+    pub user: UserWrap<U>,
+    pub active: bool,
+}
+
+/// All the logged in accounts of the realm of `U`. It implements `FromRequest` trait.
+///
+/// The accounts can not be loaded are skipped, it is empty if no user logged in.
+/// ```ignore
+/// #[get("/accounts")]
+/// async fn accounts(Accounts(accounts): Accounts<User>) -> impl Responder {
+///     accounts
+///         .iter()
+///         .map(|account| format!("{} {}", account.index, account.user.user().name))
+///         .collect::<Vec<_>>()
+///         .join("\n")
+/// }
+/// ```
+pub struct Accounts<U>(pub Vec<Account<U>>);
+
+impl<U> Accounts<U> {
+    /// The active account.
+    pub fn active(&self) -> Option<&Account<U>> {
+        self.0.iter().find(|account| account.active)
+    }
+}
+
+impl<U: 'static> FromRequest for Accounts<U>
+where
+    U: UserMinix,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let (keys, active) = LoginInfos::get(&req, U::REALM, |info| {
+                if info.pending {
+                    (Vec::new(), None)
+                } else {
+                    (info.accounts.clone(), info.active())
+                }
+            })
+            .unwrap_or_default();
+            let mut accounts = Vec::new();
+            for (index, key_str) in keys.iter().enumerate() {
+                let key = match serde_json::from_str::<U::Key>(key_str) {
+                    Ok(key) => key,
+                    Err(_) => continue,
+                };
+                if let Some(user) = U::get_user(&key, &req).await {
+                    accounts.push(Account {
+                        index,
+                        user: UserWrap(Rc::new(user)),
+                        active: Some(index) == active,
+                    });
+                }
+            }
+            Ok(Accounts(accounts))
+        })
+    }
+}
+
+/// The user logged in by `login_partial`, whose second factor is pending.
+/// It implements `FromRequest` trait for the second factor handler.
+/// ```ignore
+/// #[post("/mfa")]
+/// async fn mfa(req: HttpRequest, PendingUser(user): PendingUser<User>, form: web::Form<Code>) -> impl Responder {
+///     if user.verify_code(&form.code) {
+///         loginmanager::complete_login::<User>(&req);
+///     }
+///     HttpResponse::SeeOther().insert_header((LOCATION, safe_next(&req))).finish()
+/// }
+/// ```
+pub struct PendingUser<U>(pub UserWrap<U>);
+
+impl<U> AsRef<U> for PendingUser<U> {
+    fn as_ref(&self) -> &U {
+        self.0.as_ref()
+    }
+}
+
+impl<U: 'static> FromRequest for PendingUser<U>
+where
+    U: UserMinix,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let key_str = LoginInfos::get(&req, U::REALM, |info| {
+                info.key_str.clone().filter(|_| info.pending)
+            })
+            .flatten()
+            .ok_or_else(|| unauthorized::<U>(Reason::Unauthenticated))?;
+            let user = load_user::<U>(&req, &key_str, None)
+                .await
+                .ok_or_else(|| unauthorized::<U>(Reason::Unauthenticated))?;
+            Ok(PendingUser(UserWrap(Rc::new(user))))
         })
     }
 }
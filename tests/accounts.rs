@@ -0,0 +1,114 @@
+use actix_loginmanager as loginmanager;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use futures::future::{self, Ready};
+use loginmanager::{Accounts, CookieSession, LoginManager, UserMinix, UserWrap};
+
+struct User {
+    id: i32,
+}
+
+impl UserMinix for User {
+    type Future = Ready<Option<Self>>;
+    type Key = i32;
+
+    fn get_user(id: &i32, _: &HttpRequest) -> Self::Future {
+        future::ready(Some(User { id: *id }))
+    }
+
+    fn get_id(&self) -> &i32 {
+        &self.id
+    }
+}
+
+async fn login(req: HttpRequest, id: web::Path<i32>) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(User { id: *id }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn login_additional(req: HttpRequest, id: web::Path<i32>) -> HttpResponse {
+    loginmanager::login_additional(&UserWrap::from(User { id: *id }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn switch(req: HttpRequest, index: web::Path<usize>) -> HttpResponse {
+    if loginmanager::switch_account::<User>(&req, *index) {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn logout_one(req: HttpRequest, id: web::Path<i32>) -> HttpResponse {
+    loginmanager::logout_one(&UserWrap::from(User { id: *id }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn accounts(Accounts(accounts): Accounts<User>) -> String {
+    accounts
+        .iter()
+        .map(|account| {
+            let active = if account.active { "*" } else { "" };
+            format!("{}:{}{}", account.index, account.user.user().id, active)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+async fn current(UserWrap(user): UserWrap<User>) -> String {
+    user.id.to_string()
+}
+
+fn session_cookie<B>(res: &ServiceResponse<B>) -> Cookie<'static> {
+    res.response()
+        .cookies()
+        .find(|cookie| cookie.name() == "_session")
+        .expect("no session cookie")
+        .into_owned()
+}
+
+#[actix_web::test]
+async fn test_accounts() {
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(CookieSession::new(&[0; 32]).secure(false)))
+            .route("/login/{id}", web::get().to(login))
+            .route("/login_additional/{id}", web::get().to(login_additional))
+            .route("/switch/{index}", web::get().to(switch))
+            .route("/logout_one/{id}", web::get().to(logout_one))
+            .route("/accounts", web::get().to(accounts))
+            .route("/current", web::get().to(current)),
+    )
+    .await;
+    let call = |uri: &str, cookie: &Cookie<'static>| {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .cookie(cookie.clone())
+            .to_request();
+        app.call(req)
+    };
+
+    let req = test::TestRequest::get().uri("/accounts").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "");
+
+    let req = test::TestRequest::get().uri("/login/1").to_request();
+    let cookie = session_cookie(&test::call_service(&app, req).await);
+    let cookie = session_cookie(&call("/login_additional/2", &cookie).await.unwrap());
+    let cookie = session_cookie(&call("/login_additional/3", &cookie).await.unwrap());
+    let res = call("/accounts", &cookie).await.unwrap();
+    assert_eq!(test::read_body(res).await, "0:1,1:2,2:3*");
+    assert_eq!(test::read_body(call("/current", &cookie).await.unwrap()).await, "3");
+
+    let cookie = session_cookie(&call("/switch/0", &cookie).await.unwrap());
+    assert_eq!(test::read_body(call("/current", &cookie).await.unwrap()).await, "1");
+    assert_eq!(call("/switch/3", &cookie).await.unwrap().status(), 404);
+
+    let cookie = session_cookie(&call("/logout_one/1", &cookie).await.unwrap());
+    let res = call("/accounts", &cookie).await.unwrap();
+    assert_eq!(test::read_body(res).await, "0:2*,1:3");
+
+    let cookie = session_cookie(&call("/login/3", &cookie).await.unwrap());
+    let res = call("/accounts", &cookie).await.unwrap();
+    assert_eq!(test::read_body(res).await, "0:3*");
+}
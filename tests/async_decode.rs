@@ -0,0 +1,119 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App};
+use common::User;
+use futures::future::LocalBoxFuture;
+use loginmanager::test::login_cookie;
+use loginmanager::{
+    AsyncDecodeRequest, BoxDecoder, CookieSession, DecodeError, LoginManager, Principal, UserWrap,
+};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The API keys in a store, which is down without the keys.
+struct ApiKeys(Option<Rc<HashMap<&'static str, i32>>>);
+
+impl AsyncDecodeRequest for ApiKeys {
+    fn decode(
+        &self,
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Option<Principal>, DecodeError>> {
+        let keys = self.0.clone();
+        let api_key = req
+            .headers()
+            .get("x-api-key")
+            .and_then(|key| key.to_str().ok())
+            .map(str::to_owned);
+        Box::pin(async move {
+            let api_key = match api_key {
+                Some(api_key) => api_key,
+                None => return Ok(None),
+            };
+            let keys = keys.ok_or_else(|| DecodeError::Unavailable("store is down".to_owned()))?;
+            Ok(keys
+                .get(api_key.as_str())
+                .map(|id| Principal::new(id.to_string())))
+        })
+    }
+}
+
+async fn index(UserWrap(user): UserWrap<User>) -> String {
+    user.id.to_string()
+}
+
+#[actix_web::test]
+async fn test_async_decode() {
+    let keys = Rc::new(HashMap::from([("secret", 7)]));
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(ApiKeys(Some(keys))).redirect(false))
+            .route("/", web::get().to(index)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("x-api-key", "secret"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "7");
+
+    for key in ["wrong", ""] {
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header(("x-api-key", key))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(ApiKeys(None)).redirect(false))
+            .route("/", web::get().to(index)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("x-api-key", "secret"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+fn manager(backend: &str) -> LoginManager<BoxDecoder> {
+    match backend {
+        "api-keys" => LoginManager::boxed(ApiKeys(Some(Rc::new(HashMap::from([("secret", 7)]))))),
+        _ => LoginManager::boxed(CookieSession::new(&[0; 32]).secure(false)),
+    }
+    .redirect(false)
+}
+
+#[actix_web::test]
+async fn test_boxed() {
+    let app = test::init_service(
+        App::new()
+            .wrap(manager("api-keys"))
+            .route("/", web::get().to(index)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("x-api-key", "secret"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "7");
+
+    let app = test::init_service(
+        App::new()
+            .wrap(manager("cookie"))
+            .route("/", web::get().to(index)),
+    )
+    .await;
+    let cookie = login_cookie(&CookieSession::new(&[0; 32]), &User { id: 3 });
+    let req = test::TestRequest::get()
+        .uri("/")
+        .cookie(cookie)
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "3");
+}
@@ -0,0 +1,109 @@
+use actix_loginmanager as loginmanager;
+use actix_web::cookie::Cookie;
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use futures::future::{self, Ready};
+use loginmanager::{CookieSession, LoginManager, SharedUser, UserCache, UserMinix, UserWrap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+static LOADED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone)]
+struct User {
+    id: i32,
+}
+
+impl UserMinix for User {
+    type Future = Ready<Option<Self>>;
+    type Key = i32;
+
+    fn get_user(id: &i32, _: &HttpRequest) -> Self::Future {
+        LOADED.fetch_add(1, Ordering::SeqCst);
+        future::ready(Some(User { id: *id }))
+    }
+
+    fn get_id(&self) -> &i32 {
+        &self.id
+    }
+
+    fn to_shared(&self) -> Option<SharedUser> {
+        Some(Arc::new(self.clone()))
+    }
+
+    fn from_shared(user: &SharedUser) -> Option<Self> {
+        user.downcast_ref::<Self>().cloned()
+    }
+}
+
+async fn login(req: HttpRequest, id: web::Path<i32>) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(User { id: *id }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn current(UserWrap(user): UserWrap<User>) -> String {
+    user.id.to_string()
+}
+
+async fn invalidate(req: HttpRequest, UserWrap(user): UserWrap<User>) -> HttpResponse {
+    loginmanager::invalidate_user::<User>(&req, &user.id);
+    HttpResponse::Ok().finish()
+}
+
+#[actix_web::test]
+async fn test_user_cache() {
+    let cache = UserCache::new(Duration::from_millis(200), 2);
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                LoginManager::new(CookieSession::new(&[0; 32]).secure(false))
+                    .user_cache(cache.clone()),
+            )
+            .route("/login/{id}", web::get().to(login))
+            .route("/current", web::get().to(current))
+            .route("/invalidate", web::get().to(invalidate)),
+    )
+    .await;
+    let mut cookies = Vec::new();
+    for id in 1..=3 {
+        let req = test::TestRequest::get().uri(&format!("/login/{}", id)).to_request();
+        let res = test::call_service(&app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        cookies.push(cookie);
+    }
+    let current = |cookie: &Cookie<'static>| {
+        test::TestRequest::get()
+            .uri("/current")
+            .cookie(cookie.clone())
+            .to_request()
+    };
+
+    LOADED.store(0, Ordering::SeqCst);
+    assert_eq!(test::call_and_read_body(&app, current(&cookies[0])).await, "1");
+    assert_eq!(test::call_and_read_body(&app, current(&cookies[0])).await, "1");
+    assert_eq!(LOADED.load(Ordering::SeqCst), 1);
+
+    // The least recently used user 1 is dropped.
+    test::call_service(&app, current(&cookies[1])).await;
+    test::call_service(&app, current(&cookies[2])).await;
+    assert_eq!(cache.len(), 2);
+    assert_eq!(LOADED.load(Ordering::SeqCst), 3);
+    test::call_service(&app, current(&cookies[0])).await;
+    assert_eq!(LOADED.load(Ordering::SeqCst), 4);
+
+    let req = test::TestRequest::get()
+        .uri("/invalidate")
+        .cookie(cookies[0].clone())
+        .to_request();
+    test::call_service(&app, req).await;
+    test::call_service(&app, current(&cookies[0])).await;
+    assert_eq!(LOADED.load(Ordering::SeqCst), 5);
+
+    actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+    test::call_service(&app, current(&cookies[0])).await;
+    assert_eq!(LOADED.load(Ordering::SeqCst), 6);
+
+    cache.invalidate_user::<User>(&1);
+    test::call_service(&app, current(&cookies[0])).await;
+    assert_eq!(LOADED.load(Ordering::SeqCst), 7);
+}
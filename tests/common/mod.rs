@@ -0,0 +1,34 @@
+//! The fixture shared by the integration tests.
+#![allow(dead_code)]
+
+use actix_loginmanager::UserMinix;
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::HttpRequest;
+use futures::future::{self, Ready};
+
+pub struct User {
+    pub id: i32,
+}
+
+impl UserMinix for User {
+    type Future = Ready<Option<Self>>;
+    type Key = i32;
+
+    fn get_user(id: &i32, _: &HttpRequest) -> Self::Future {
+        future::ready(Some(User { id: *id }))
+    }
+
+    fn get_id(&self) -> &i32 {
+        &self.id
+    }
+}
+
+/// The `_session` cookie the response sets.
+pub fn session_cookie<B>(res: &ServiceResponse<B>) -> Cookie<'static> {
+    res.response()
+        .cookies()
+        .find(|cookie| cookie.name() == "_session")
+        .expect("no session cookie")
+        .into_owned()
+}
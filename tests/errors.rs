@@ -0,0 +1,217 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ResponseHead, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::header::{LOCATION, SET_COOKIE};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{test, web, App, Error, HttpRequest, HttpResponse};
+use common::User;
+use loginmanager::test::{assert_logged_in, TestRequestExt};
+use loginmanager::{
+    CookieSession, CurrentUser, DecodeRequest, LoginInfo, LoginManager, LoginManagerError, Reason,
+    RequireLogin, Unauthorized, UserWrap,
+};
+
+/// Decodes the user 1, but can not write any session.
+struct Broken;
+
+impl DecodeRequest for Broken {
+    fn decode(&self, _: &ServiceRequest) -> Option<String> {
+        Some("1".to_owned())
+    }
+
+    fn update_(
+        &self,
+        _: Option<&LoginInfo>,
+        _: &HttpRequest,
+        _: &mut ResponseHead,
+    ) -> Result<(), Error> {
+        Err(ErrorInternalServerError("the store is down"))
+    }
+}
+
+async fn index(UserWrap(user): UserWrap<User>) -> String {
+    user.id.to_string()
+}
+
+async fn login(req: HttpRequest) -> Result<HttpResponse, Error> {
+    loginmanager::login(&UserWrap::from(User { id: 1 }), &req);
+    Err(ErrorInternalServerError("failed after login"))
+}
+
+async fn login_ok(req: HttpRequest) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(User { id: 1 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn forbidden(req: HttpRequest) -> Result<HttpResponse, Error> {
+    loginmanager::login(&UserWrap::from(User { id: 1 }), &req);
+    Err(Unauthorized::new(Reason::Unauthenticated).into())
+}
+
+async fn current(user: CurrentUser<User>) -> String {
+    user.is_authenticated().to_string()
+}
+
+#[actix_web::test]
+async fn test_try_views() {
+    let manager = || LoginManager::new(CookieSession::new(&[0; 32]));
+    assert!(matches!(
+        manager().try_login_view("login"),
+        Err(LoginManagerError::RelativeView(_))
+    ));
+    assert!(matches!(
+        manager().try_mfa_view("/mfa\n"),
+        Err(LoginManagerError::InvalidUrl(_))
+    ));
+    for url in ["/登录", "/log in"] {
+        assert!(matches!(
+            manager().try_login_view(url),
+            Err(LoginManagerError::InvalidUrl(_))
+        ));
+    }
+    assert!(manager().try_login_view("/%E7%99%BB%E5%BD%95").is_ok());
+    assert!(manager().try_login_view("/account/login?from=app").is_ok());
+    assert!(manager()
+        .try_login_view("https://sso.example.com/login")
+        .is_ok());
+
+    let manager = manager().mfa_view("/mfa\n".to_owned());
+    assert!(manager.new_transform(test::ok_service()).await.is_err());
+    let manager = LoginManager::new(CookieSession::new(&[0; 32])).mfa_view("/验证".to_owned());
+    assert!(manager.new_transform(test::ok_service()).await.is_err());
+}
+
+#[actix_web::test]
+async fn test_update_error() {
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(Broken))
+            .route("/", web::get().to(index)),
+    )
+    .await;
+    let req = test::TestRequest::get().uri("/").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[actix_web::test]
+async fn test_missing_middleware() {
+    let app = test::init_service(
+        App::new()
+            .route("/", web::get().to(index))
+            .route("/current", web::get().to(current))
+            .route("/public", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+    for uri in ["/", "/current"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let req = test::TestRequest::get().uri("/public").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_error_path() {
+    let app = test::init_service(
+        App::new()
+            // The errors of the inner middlewares are not responses yet.
+            .wrap_fn(|req, srv| {
+                let path = req.path().to_owned();
+                let fut = srv.call(req);
+                async move {
+                    let res = fut.await?;
+                    match path.as_str() {
+                        "/fail" => Err(ErrorInternalServerError("failed after the handler")),
+                        _ => Ok(res),
+                    }
+                }
+            })
+            .wrap(LoginManager::new(
+                CookieSession::new(&[0; 32]).secure(false),
+            ))
+            .route("/login", web::post().to(login))
+            .route("/forbidden", web::post().to(forbidden))
+            .route("/fail", web::post().to(login_ok)),
+    )
+    .await;
+    let req = test::TestRequest::post().uri("/login").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(res.headers().contains_key(SET_COOKIE));
+
+    let req = test::TestRequest::post().uri("/forbidden").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(
+        res.headers().get(LOCATION).unwrap(),
+        "/login?next=%2Fforbidden"
+    );
+    assert!(res.headers().contains_key(SET_COOKIE));
+
+    // The request is gone, the error is left to actix-web.
+    let req = test::TestRequest::post().uri("/fail").to_request();
+    assert!(app.call(req).await.is_err());
+}
+
+#[actix_web::test]
+async fn test_login_before_routing() {
+    let app = test::init_service(
+        App::new()
+            .wrap_fn(|req, srv| {
+                loginmanager::login(&UserWrap::from(User { id: 1 }), req.request());
+                srv.call(req)
+            })
+            .wrap(LoginManager::new(
+                CookieSession::new(&[0; 32]).secure(false),
+            ))
+            .route("/", web::get().to(index))
+            .service(
+                web::scope("/admin")
+                    .wrap(RequireLogin::<User>::new())
+                    .route("", web::get().to(index)),
+            ),
+    )
+    .await;
+    for uri in ["/", "/admin"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.headers().contains_key(SET_COOKIE));
+        assert_eq!(test::read_body(res).await, "1");
+    }
+}
+
+/// Load the user before the routing of the scope, and log in the next one.
+async fn login_next(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let UserWrap(user) = req.extract::<UserWrap<User>>().await?;
+    loginmanager::login(&UserWrap::from(User { id: user.id + 1 }), req.request());
+    next.call(req).await
+}
+
+#[actix_web::test]
+async fn test_scope_root() {
+    let session = || CookieSession::new(&[0; 32]).secure(false);
+    let app = test::init_service(
+        App::new().wrap(LoginManager::new(session())).service(
+            web::scope("/admin")
+                .wrap(from_fn(login_next))
+                .route("", web::get().to(index)),
+        ),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/admin")
+        .login_as(&session(), &User { id: 1 })
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_logged_in(&session(), &res, &User { id: 2 });
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::http::{header, StatusCode};
+use actix_web::{test, web, App, HttpRequest};
+use common::User;
+use futures::future::{self, Ready};
+use loginmanager::test::{assert_logged_in, assert_logged_out, TestRequestExt};
+use loginmanager::{
+    AuthError, Authenticator, CookieSession, LoginHandler, LoginManager, LogoutHandler,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Credentials {
+    name: String,
+    password: String,
+}
+
+struct Auth;
+
+impl Authenticator for Auth {
+    type User = User;
+    type Credentials = Credentials;
+    type Future = Ready<Result<User, AuthError>>;
+
+    fn authenticate(&self, credentials: Credentials, _: &HttpRequest) -> Self::Future {
+        future::ready(
+            match (credentials.name.as_str(), credentials.password.as_str()) {
+                ("tom", "secret") => Ok(User { id: 1 }),
+                ("jerry", "secret") => Err(AuthError::Inactive),
+                _ => Err(AuthError::InvalidCredentials),
+            },
+        )
+    }
+}
+
+fn session() -> CookieSession {
+    CookieSession::new(&[0; 32]).secure(false)
+}
+
+#[actix_web::test]
+async fn test_handlers() {
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(session()))
+            .service(LoginHandler::new(Auth).route(web::get().to(|| async { "login page" })))
+            .service(LogoutHandler::<User>::new()),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/login").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "login page");
+
+    let req = test::TestRequest::post()
+        .uri("/login?next=%2Fhome")
+        .set_form([("name", "tom"), ("password", "secret")])
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/home");
+    assert_logged_in(&session(), &res, &User { id: 1 });
+
+    let req = test::TestRequest::post()
+        .uri("/login?next=https%3A%2F%2Fevil.com")
+        .set_json(serde_json::json!({"name": "tom", "password": "secret"}))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_logged_in(&session(), &res, &User { id: 1 });
+    assert_eq!(test::read_body(res).await, r#"{"next":"/"}"#);
+
+    let req = test::TestRequest::post()
+        .uri("/login?error=inactive&next=%2Fhome")
+        .set_form([("name", "tom"), ("password", "wrong")])
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        res.headers().get(header::LOCATION).unwrap(),
+        "/login?error=invalid_credentials&next=%2Fhome"
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .set_json(serde_json::json!({"name": "jerry", "password": "secret"}))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["error"], "inactive");
+
+    let req = test::TestRequest::get()
+        .uri("/logout")
+        .login_as(&session(), &User { id: 1 })
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    let req = test::TestRequest::post()
+        .uri("/logout")
+        .login_as(&session(), &User { id: 1 })
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/");
+    assert_logged_out(&session(), &res);
+}
+
+#[actix_web::test]
+async fn test_login_path() {
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(session()))
+            .service(LoginHandler::new(Auth).path("/api/login")),
+    )
+    .await;
+    let req = test::TestRequest::post()
+        .uri("/api/login")
+        .set_json(serde_json::json!({"name": "tom", "password": "wrong"}))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["error"], "invalid_credentials");
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::cookie::Cookie;
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App, FromRequest, HttpRequest, HttpResponse};
+use common::User;
+use loginmanager::test::{logged_in_key, login_cookie};
+use loginmanager::{
+    Accounts, BlockImpersonation, CookieSession, CurrentUser, Impersonation, Impersonator,
+    LoginManager, MemoryRegistry, SessionRegistry, UserWrap,
+};
+use std::sync::{Arc, Mutex};
+
+async fn impersonate(req: HttpRequest, id: web::Path<i32>) -> HttpResponse {
+    if loginmanager::impersonate(&req, &UserWrap::from(User { id: *id })) {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::BadRequest().finish()
+    }
+}
+
+async fn stop(req: HttpRequest) -> HttpResponse {
+    if loginmanager::stop_impersonating::<User>(&req) {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::BadRequest().finish()
+    }
+}
+
+async fn index(UserWrap(user): UserWrap<User>, impersonator: Impersonator<User>) -> String {
+    match impersonator.user() {
+        Some(admin) => format!("{} as {}", admin.id, user.id),
+        None => user.id.to_string(),
+    }
+}
+
+async fn try_impersonate(req: HttpRequest) -> String {
+    let impersonated = loginmanager::impersonate(&req, &UserWrap::from(User { id: 7 }));
+    let user = CurrentUser::<User>::extract(&req).await.unwrap();
+    format!("{} {:?}", impersonated, user.user().map(|user| user.id))
+}
+
+async fn add(req: HttpRequest, id: web::Path<i32>) -> HttpResponse {
+    loginmanager::login_additional(&UserWrap::from(User { id: *id }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn accounts(Accounts(accounts): Accounts<User>) -> String {
+    accounts
+        .iter()
+        .map(|account| {
+            format!(
+                "{}{}",
+                account.user.user().id,
+                if account.active { "*" } else { "" }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn session() -> CookieSession {
+    CookieSession::new(&[0; 32]).secure(false)
+}
+
+#[actix_web::test]
+async fn test_impersonation() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let audit = events.clone();
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(session()).on_impersonation(
+                move |_, event, impersonator, user| {
+                    let event = format!("{:?} {} {}", event, impersonator, user);
+                    audit.lock().unwrap().push(event);
+                },
+            ))
+            .route("/", web::get().to(index))
+            .route("/impersonate/{id}", web::get().to(impersonate))
+            .route("/stop", web::get().to(stop))
+            .service(
+                web::scope("/sensitive")
+                    .wrap(BlockImpersonation::<User>::new())
+                    .route("", web::get().to(HttpResponse::Ok)),
+            ),
+    )
+    .await;
+    let call = |uri: &str, cookie| {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .cookie(cookie)
+            .to_request();
+        test::call_service(&app, req)
+    };
+
+    let req = test::TestRequest::get().uri("/impersonate/2").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    let admin = login_cookie(&session(), &User { id: 1 });
+    let res = call("/impersonate/2", admin.clone()).await;
+    assert_eq!(logged_in_key(&session(), &res).as_deref(), Some("2"));
+    let cookie = res
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "_session")
+        .unwrap()
+        .into_owned();
+    assert_eq!(
+        test::read_body(call("/", cookie.clone()).await).await,
+        "1 as 2"
+    );
+    assert_eq!(
+        call("/sensitive", cookie.clone()).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        call("/sensitive", admin.clone()).await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(test::read_body(call("/", admin.clone()).await).await, "1");
+
+    let res = call("/stop", cookie).await;
+    assert_eq!(logged_in_key(&session(), &res).as_deref(), Some("1"));
+    assert_eq!(call("/stop", admin).await.status(), StatusCode::BAD_REQUEST);
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            format!("{:?} 1 2", Impersonation::Start),
+            format!("{:?} 1 2", Impersonation::Stop),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn test_impersonation_accounts() {
+    let registry = MemoryRegistry::new();
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(session()).session_registry(registry.clone()))
+            .route("/try", web::get().to(try_impersonate))
+            .route("/add/{id}", web::get().to(add))
+            .route("/accounts", web::get().to(accounts))
+            .route("/impersonate/{id}", web::get().to(impersonate))
+            .route("/stop", web::get().to(stop)),
+    )
+    .await;
+    let req = test::TestRequest::get().uri("/try").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "false None");
+
+    let call = |uri: &str, cookie: Cookie<'static>| {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .cookie(cookie.clone())
+            .to_request();
+        let res = test::call_service(&app, req);
+        async move {
+            let res = res.await;
+            let cookie = match res.response().cookies().find(|c| c.name() == "_session") {
+                Some(cookie) => cookie.into_owned(),
+                None => cookie,
+            };
+            (cookie, test::read_body(res).await)
+        }
+    };
+    let (cookie, _) = call("/add/1", Cookie::named("_session")).await;
+    let (cookie, _) = call("/add/3", cookie).await;
+    let session_id = || registry.sessions("default", "3")[0].id.clone();
+    let id = session_id();
+
+    let (cookie, _) = call("/impersonate/2", cookie).await;
+    assert_ne!(session_id(), id);
+    let id = session_id();
+    let (cookie, accounts) = call("/accounts", cookie).await;
+    assert_eq!(accounts, "1 3");
+
+    let (cookie, _) = call("/stop", cookie).await;
+    assert_ne!(session_id(), id);
+    assert_eq!(call("/accounts", cookie).await.1, "1 3*");
+}
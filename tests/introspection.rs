@@ -0,0 +1,136 @@
+use actix_loginmanager as loginmanager;
+use actix_web::http::{header::AUTHORIZATION, StatusCode};
+use actix_web::{test, web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use futures::future::{self, Ready};
+use loginmanager::introspection::{Introspection, TokenInfo};
+use loginmanager::{LoginManager, UserMinix, UserWrap};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct User {
+    id: String,
+}
+
+impl UserMinix for User {
+    type Future = Ready<Option<Self>>;
+    type Key = String;
+
+    fn get_user(id: &String, _: &HttpRequest) -> Self::Future {
+        future::ready(Some(User { id: id.clone() }))
+    }
+
+    fn get_id(&self) -> &String {
+        &self.id
+    }
+}
+
+#[derive(Deserialize)]
+struct IntrospectForm {
+    token: String,
+}
+
+async fn introspect(
+    req: HttpRequest,
+    calls: web::Data<AtomicUsize>,
+    form: web::Form<IntrospectForm>,
+) -> HttpResponse {
+    calls.fetch_add(1, Ordering::SeqCst);
+    // "api:secret" in basic authentication
+    if req.headers().get(AUTHORIZATION).unwrap() != "Basic YXBpOnNlY3JldA==" {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let exp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 60;
+    match form.token.as_str() {
+        "reader" | "other" => HttpResponse::Ok().json(json!({
+            "active": true, "sub": "tom", "scope": "orders:read profile", "exp": exp,
+        })),
+        _ => HttpResponse::Ok().json(json!({"active": false})),
+    }
+}
+
+async fn orders(UserWrap(user): UserWrap<User>, token: TokenInfo) -> Result<String, Error> {
+    token.require_scope("orders:read")?;
+    Ok(format!("orders of {}", user.id))
+}
+
+async fn write_orders(_: UserWrap<User>, token: TokenInfo) -> Result<String, Error> {
+    token.require_scope("orders:write")?;
+    Ok("written".to_owned())
+}
+
+#[actix_web::test]
+async fn test_introspection() {
+    let calls = web::Data::new(AtomicUsize::new(0));
+    let server_calls = calls.clone();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}/introspect", listener.local_addr().unwrap());
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(server_calls.clone())
+            .route("/introspect", web::post().to(introspect))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+
+    let introspection = Introspection::new(&endpoint, "api", "secret");
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(introspection.clone()).redirect(false))
+            .route("/orders", web::get().to(orders))
+            .route("/orders", web::post().to(write_orders)),
+    )
+    .await;
+    let request = |token: Option<&str>| {
+        let mut req = test::TestRequest::get().uri("/orders");
+        if let Some(token) = token {
+            req = req.insert_header((AUTHORIZATION, format!("Bearer {}", token)));
+        }
+        req
+    };
+
+    let res = test::call_service(&app, request(None).to_request()).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    let req = request(Some("reader")).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "orders of tom");
+    let req = request(Some("reader")).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "orders of tom");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let req = request(Some("reader"))
+        .method(actix_web::http::Method::POST)
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::FORBIDDEN
+    );
+
+    for _ in 0..2 {
+        let res = test::call_service(&app, request(Some("revoked")).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // The least recently used token is dropped.
+    let introspection = Introspection::new(&endpoint, "api", "secret").max_size(1);
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(introspection).redirect(false))
+            .route("/orders", web::get().to(orders)),
+    )
+    .await;
+    for (token, total) in [("reader", 4), ("other", 5), ("other", 5), ("reader", 6)] {
+        let req = request(Some(token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), total);
+    }
+}
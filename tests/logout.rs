@@ -0,0 +1,65 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::cookie::{time::Duration, Cookie};
+use actix_web::dev::{ServiceResponse, Transform};
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use common::User;
+use loginmanager::test::login_cookie;
+use loginmanager::{CookieSession, LoginManager, UserWrap};
+
+async fn logout(req: HttpRequest, user: UserWrap<User>) -> HttpResponse {
+    loginmanager::logout(&user, &req);
+    HttpResponse::Ok().finish()
+}
+
+fn session() -> CookieSession {
+    CookieSession::new(&[0; 32])
+        .secure(false)
+        .domain(Some("example.com".to_owned()))
+}
+
+fn cookie<B>(res: &ServiceResponse<B>, name: &str) -> Cookie<'static> {
+    res.response()
+        .cookies()
+        .find(|cookie| cookie.name() == name)
+        .unwrap_or_else(|| panic!("no cookie {}", name))
+        .into_owned()
+}
+
+#[actix_web::test]
+async fn test_logout() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                LoginManager::new(session())
+                    .clear_cookie(Cookie::new("csrf", ""))
+                    .clear_cookie(Cookie::build("remember_me", "").path("/account").finish())
+                    .clear_site_data(&["cache", "cookies"]),
+            )
+            .route("/logout", web::post().to(logout)),
+    )
+    .await;
+    let req = test::TestRequest::post()
+        .uri("/logout")
+        .cookie(login_cookie(&session(), &User { id: 1 }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+
+    let removal = cookie(&res, "_session");
+    assert_eq!(removal.value(), "");
+    assert_eq!(removal.max_age(), Some(Duration::ZERO));
+    assert_eq!(removal.path(), Some("/"));
+    assert_eq!(removal.domain(), Some("example.com"));
+    assert_eq!(cookie(&res, "csrf").path(), Some("/"));
+    let remember_me = cookie(&res, "remember_me");
+    assert_eq!(remember_me.max_age(), Some(Duration::ZERO));
+    assert_eq!(remember_me.path(), Some("/account"));
+    assert_eq!(
+        res.headers().get("clear-site-data").unwrap(),
+        "\"cache\", \"cookies\""
+    );
+
+    let manager = LoginManager::new(session()).clear_site_data(&["cache\""]);
+    assert!(manager.new_transform(test::ok_service()).await.is_err());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::cookie::Cookie;
+use actix_web::http::{header::LOCATION, StatusCode};
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use common::{session_cookie, User};
+use loginmanager::test::logged_in_key;
+use loginmanager::{CookieSession, LoginManager, PendingUser, UserWrap};
+
+async fn login(req: HttpRequest) -> HttpResponse {
+    loginmanager::login_partial(&UserWrap::from(User { id: 1 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn mfa(req: HttpRequest, PendingUser(user): PendingUser<User>) -> HttpResponse {
+    assert_eq!(user.user().id, 1);
+    loginmanager::complete_login::<User>(&req);
+    HttpResponse::Ok().finish()
+}
+
+async fn index(UserWrap(user): UserWrap<User>) -> String {
+    user.id.to_string()
+}
+
+fn session() -> CookieSession {
+    CookieSession::new(&[0; 32]).secure(false)
+}
+
+#[actix_web::test]
+async fn test_mfa() {
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(session()).mfa_view("/mfa".to_owned()))
+            .route("/", web::get().to(index))
+            .route("/login", web::get().to(login))
+            .route("/mfa", web::get().to(mfa)),
+    )
+    .await;
+    let call = |uri: &str, cookie: Option<Cookie<'static>>| {
+        let mut req = test::TestRequest::get().uri(uri);
+        if let Some(cookie) = cookie {
+            req = req.cookie(cookie);
+        }
+        test::call_service(&app, req.to_request())
+    };
+
+    let res = call("/", None).await;
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "/login?next=%2F");
+    assert_eq!(call("/mfa", None).await.status(), StatusCode::FOUND);
+
+    let res = call("/login", None).await;
+    assert_eq!(logged_in_key(&session(), &res).as_deref(), Some("1"));
+    let pending = session_cookie(&res);
+    let res = call("/", Some(pending.clone())).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "/mfa?next=%2F");
+
+    let res = call("/mfa", Some(pending)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookie = session_cookie(&res);
+    assert_eq!(
+        test::read_body(call("/", Some(cookie.clone())).await).await,
+        "1"
+    );
+    assert_eq!(call("/mfa", Some(cookie)).await.status(), StatusCode::FOUND);
+}
@@ -0,0 +1,244 @@
+use actix_loginmanager as loginmanager;
+use actix_web::http::{header::LOCATION, StatusCode};
+use actix_web::{test, web, App, HttpRequest, HttpResponse, HttpServer};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use futures::future::{self, Ready};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use loginmanager::oidc::{Claims, OidcError, OidcProvider};
+use loginmanager::test::logged_in_key;
+use loginmanager::{CookieSession, LoginManager, MemoryRegistry, SessionLimit, UserMinix};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const KEY: &str = include_str!("oidc_key.pem");
+const MODULUS: &str = "2KuDYreBRWKfD1ntF9K2ZsOKjc8w6SHTjVJeYKwd2YukQnXqAvetiCF1_l3LWlDeYunLUaGXPKX8Pf5eGkqsosT25bBnnn1cxWmckbqGf4bAeskPDgUpdvFvcWqoshS6MIGOdMZkK9-06iha3Dqt6vzhxOf1PX6QRRCGS3vyho_L9opNixtW533-g8S61iRE00oJUhIR2_0SsSs5AnvBBmHqrnhnQ3dHeWltFXDJt_uJcDY_s3TweR01MGArwvCtspyfSffO_1lIc0HCzICyMIeEY0ZLX72CSwZ9-6zXTtREgABJ3sHRYbEe_J_iDPVvpm7t6i-Va3XuYPfrozxWtQ";
+
+struct User {
+    id: String,
+}
+
+impl UserMinix for User {
+    type Future = Ready<Option<Self>>;
+    type Key = String;
+
+    fn get_user(id: &String, _: &HttpRequest) -> Self::Future {
+        future::ready(Some(User { id: id.clone() }))
+    }
+
+    fn get_id(&self) -> &String {
+        &self.id
+    }
+}
+
+/// The nonce and the code challenge of the issued codes.
+type Codes = Arc<Mutex<HashMap<String, (String, String)>>>;
+
+#[derive(Deserialize)]
+struct TokenForm {
+    code: String,
+    code_verifier: String,
+}
+
+async fn token(
+    codes: web::Data<Codes>,
+    issuer: web::Data<String>,
+    form: web::Form<TokenForm>,
+) -> HttpResponse {
+    let (nonce, challenge) = match codes.lock().unwrap().remove(&form.code) {
+        Some(code) => code,
+        None => return HttpResponse::BadRequest().json(json!({"error": "invalid_grant"})),
+    };
+    let mut sha256 = Sha256::new();
+    sha256.input_str(&form.code_verifier);
+    let mut hash = [0u8; 32];
+    sha256.result(&mut hash);
+    if base64_url(&hash) != challenge {
+        return HttpResponse::BadRequest().json(json!({"error": "invalid_grant"}));
+    }
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("test".to_owned());
+    let claims = json!({
+        "iss": issuer.as_str(),
+        "aud": "client",
+        "sub": "tom",
+        "email": "tom@example.com",
+        "nonce": nonce,
+        "exp": now() + 300,
+        "iat": now(),
+    });
+    let key = EncodingKey::from_rsa_pem(KEY.as_bytes()).unwrap();
+    let id_token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+    HttpResponse::Ok()
+        .json(json!({"access_token": "at", "token_type": "Bearer", "id_token": id_token}))
+}
+
+async fn jwks() -> HttpResponse {
+    HttpResponse::Ok().json(json!({"keys": [{
+        "kty": "RSA", "kid": "test", "alg": "RS256", "use": "sig", "n": MODULUS, "e": "AQAB",
+    }]}))
+}
+
+async fn metadata(issuer: web::Data<String>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "issuer": issuer.as_str(),
+        "authorization_endpoint": format!("{}/auth", issuer.as_str()),
+        "token_endpoint": format!("{}/token", issuer.as_str()),
+        "jwks_uri": format!("{}/keys", issuer.as_str()),
+    }))
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn query(url: &str) -> HashMap<String, String> {
+    let query = url.split_once('?').unwrap().1;
+    web::Query::<HashMap<String, String>>::from_query(query)
+        .unwrap()
+        .into_inner()
+}
+
+async fn callback(
+    req: HttpRequest,
+    provider: web::Data<OidcProvider>,
+) -> Result<HttpResponse, OidcError> {
+    provider
+        .callback(&req, |claims: Claims| async move {
+            assert_eq!(claims.email.as_deref(), Some("tom@example.com"));
+            Some(User { id: claims.sub })
+        })
+        .await
+}
+
+#[actix_web::test]
+async fn test_oidc() {
+    let codes = Codes::default();
+    let provider_codes = codes.clone();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let provider_issuer = issuer.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(provider_codes.clone()))
+            .app_data(web::Data::new(provider_issuer.clone()))
+            .route("/token", web::post().to(token))
+            .route("/keys", web::get().to(jwks))
+            .route("/.well-known/openid-configuration", web::get().to(metadata))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+
+    let provider = OidcProvider::new(&issuer, "client", "http://app/callback", &[0; 32])
+        .client_secret("secret")
+        .secure(false)
+        .discover()
+        .await
+        .unwrap();
+    let provider = web::Data::new(provider);
+    let session = || CookieSession::new(&[1; 32]).secure(false);
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                LoginManager::new(session())
+                    .session_registry(MemoryRegistry::new())
+                    .max_sessions_per_user(1, SessionLimit::Reject),
+            )
+            .app_data(provider.clone())
+            .route(
+                "/login",
+                web::get().to(
+                    |req: HttpRequest, provider: web::Data<OidcProvider>| async move {
+                        provider.authorize(&req)
+                    },
+                ),
+            )
+            .route("/callback", web::get().to(callback)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/login?next=%2Fhome")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    let location = res.headers().get(LOCATION).unwrap().to_str().unwrap();
+    assert!(location.starts_with(&format!("{}/auth?", issuer)));
+    let params = query(location);
+    assert_eq!(params["client_id"], "client");
+    assert_eq!(params["code_challenge_method"], "S256");
+    assert_eq!(params["scope"], "openid email profile");
+    let flow = res
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "_oidc")
+        .unwrap()
+        .into_owned();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/callback?code=abc&state={}", "forged"))
+        .cookie(flow.clone())
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    codes.lock().unwrap().insert(
+        "abc".to_owned(),
+        (params["nonce"].clone(), params["code_challenge"].clone()),
+    );
+    let req = test::TestRequest::get()
+        .uri(&format!("/callback?code=abc&state={}", params["state"]))
+        .cookie(flow.clone())
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "/home");
+    assert_eq!(logged_in_key(&session(), &res).as_deref(), Some("\"tom\""));
+
+    // The code is used once.
+    let req = test::TestRequest::get()
+        .uri(&format!("/callback?code=abc&state={}", params["state"]))
+        .cookie(flow)
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::BAD_GATEWAY
+    );
+
+    // The second browser is over the session limit.
+    let req = test::TestRequest::get().uri("/login").to_request();
+    let res = test::call_service(&app, req).await;
+    let params = query(res.headers().get(LOCATION).unwrap().to_str().unwrap());
+    let flow = res
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "_oidc")
+        .unwrap()
+        .into_owned();
+    codes.lock().unwrap().insert(
+        "def".to_owned(),
+        (params["nonce"].clone(), params["code_challenge"].clone()),
+    );
+    let req = test::TestRequest::get()
+        .uri(&format!("/callback?code=def&state={}", params["state"]))
+        .cookie(flow)
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(logged_in_key(&session(), &res), None);
+}
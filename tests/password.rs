@@ -0,0 +1,32 @@
+use actix_loginmanager::password::{Passwords, Verified};
+
+const BCRYPT: &str = "$2b$04$iMxTDtwblvW8iePwlBd2ZuW3a8ByXfHELFcNq/eXnijwy59IWXBrK";
+const PBKDF2: &str =
+    "$pbkdf2-sha256$i=1000,l=32$c2FsdHNhbHRzYWx0c2FsdA$RilxBxnvGa3JIyaXwlUUKmvuPzxjHerJeqIuhiIvKNU";
+
+#[test]
+fn test_password() {
+    let passwords = Passwords::new().memory_cost(1024).time_cost(1);
+    let hash = passwords.hash("hunter2").unwrap();
+    assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+    assert_ne!(hash, passwords.hash("hunter2").unwrap());
+    assert_eq!(passwords.verify("hunter2", &hash), Verified::Valid);
+    assert_eq!(passwords.verify("hunter3", &hash), Verified::Invalid);
+    assert_eq!(passwords.verify("hunter2", "not a hash"), Verified::Invalid);
+
+    let stronger = Passwords::new().memory_cost(2048).time_cost(1);
+    assert_eq!(stronger.verify("hunter2", &hash), Verified::NeedsRehash);
+    assert!(stronger.verify("hunter2", &hash).is_valid());
+
+    assert_eq!(passwords.verify("hunter2", BCRYPT), Verified::NeedsRehash);
+    assert_eq!(passwords.verify("hunter3", BCRYPT), Verified::Invalid);
+    assert_eq!(passwords.verify("hunter2", PBKDF2), Verified::NeedsRehash);
+    assert_eq!(passwords.verify("hunter3", PBKDF2), Verified::Invalid);
+
+    assert_eq!(
+        passwords.verify_user("hunter2", Some(&hash)),
+        Verified::Valid
+    );
+    assert_eq!(passwords.verify_user("hunter2", None), Verified::Invalid);
+    assert_eq!(stronger.verify_user("hunter2", None), Verified::Invalid);
+}
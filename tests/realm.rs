@@ -0,0 +1,124 @@
+use actix_loginmanager as loginmanager;
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::{header::LOCATION, StatusCode};
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use futures::future::{self, Ready};
+use loginmanager::{CookieSession, LoginManager, UserMinix, UserWrap};
+
+struct Admin {
+    id: i32,
+}
+
+impl UserMinix for Admin {
+    type Future = Ready<Option<Self>>;
+    type Key = i32;
+    const REALM: &'static str = "admin";
+
+    fn get_user(id: &i32, _: &HttpRequest) -> Self::Future {
+        future::ready(Some(Admin { id: *id }))
+    }
+
+    fn get_id(&self) -> &i32 {
+        &self.id
+    }
+}
+
+struct Customer {
+    id: i32,
+}
+
+impl UserMinix for Customer {
+    type Future = Ready<Option<Self>>;
+    type Key = i32;
+    const REALM: &'static str = "customer";
+
+    fn get_user(id: &i32, _: &HttpRequest) -> Self::Future {
+        future::ready(Some(Customer { id: *id }))
+    }
+
+    fn get_id(&self) -> &i32 {
+        &self.id
+    }
+}
+
+async fn admin_login(req: HttpRequest) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(Admin { id: 1 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn customer_login(req: HttpRequest) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(Customer { id: 2 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn admin(UserWrap(admin): UserWrap<Admin>) -> String {
+    format!("admin {}", admin.id)
+}
+
+async fn customer(UserWrap(customer): UserWrap<Customer>) -> String {
+    format!("customer {}", customer.id)
+}
+
+fn cookie<B>(res: &ServiceResponse<B>, name: &str) -> Option<Cookie<'static>> {
+    res.response()
+        .cookies()
+        .find(|cookie| cookie.name() == name)
+        .map(Cookie::into_owned)
+}
+
+#[actix_web::test]
+async fn test_realms() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                LoginManager::new(CookieSession::new(&[1; 32]).name("_admin").secure(false))
+                    .realm("admin")
+                    .login_view("/admin/login".to_owned()),
+            )
+            .wrap(
+                LoginManager::new(CookieSession::new(&[2; 32]).name("_customer").secure(false))
+                    .realm("customer")
+                    .login_view("/customer/login".to_owned()),
+            )
+            .route("/admin/login", web::get().to(admin_login))
+            .route("/customer/login", web::get().to(customer_login))
+            .route("/admin", web::get().to(admin))
+            .route("/customer", web::get().to(customer)),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/admin").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "/admin/login?next=%2Fadmin");
+    let req = test::TestRequest::get().uri("/customer").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "/customer/login?next=%2Fcustomer");
+
+    let req = test::TestRequest::get().uri("/admin/login").to_request();
+    let res = test::call_service(&app, req).await;
+    let admin_cookie = cookie(&res, "_admin").unwrap();
+    assert!(cookie(&res, "_customer").is_none());
+
+    let req = test::TestRequest::get()
+        .uri("/admin")
+        .cookie(admin_cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "admin 1");
+    let req = test::TestRequest::get()
+        .uri("/customer")
+        .cookie(admin_cookie.clone())
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+
+    let req = test::TestRequest::get().uri("/customer/login").to_request();
+    let customer_cookie = cookie(&test::call_service(&app, req).await, "_customer").unwrap();
+    let req = test::TestRequest::get()
+        .uri("/customer")
+        .cookie(admin_cookie)
+        .cookie(customer_cookie)
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "customer 2");
+}
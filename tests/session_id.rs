@@ -0,0 +1,79 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::http::header::USER_AGENT;
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use common::{session_cookie, User};
+use loginmanager::test::session_id;
+use loginmanager::{CookieSession, LoginManager, UserWrap};
+
+async fn login(req: HttpRequest) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(User { id: 1 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn password(req: HttpRequest, _: UserWrap<User>) -> HttpResponse {
+    loginmanager::rotate_session(&req);
+    HttpResponse::Ok().finish()
+}
+
+async fn index(UserWrap(user): UserWrap<User>) -> String {
+    user.id.to_string()
+}
+
+fn session() -> CookieSession {
+    CookieSession::new(&[0; 32]).secure(false)
+}
+
+#[actix_web::test]
+async fn test_session_id() {
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(session()))
+            .route("/login", web::post().to(login))
+            .route("/password", web::post().to(password))
+            .route("/", web::get().to(index)),
+    )
+    .await;
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let req = test::TestRequest::post().uri("/login").to_request();
+        let res = test::call_service(&app, req).await;
+        ids.push(session_id(&session(), &res).unwrap());
+    }
+    assert_ne!(ids[0], ids[1]);
+    assert_eq!(ids[0].len(), 32);
+
+    // A new id on the login of the fixed session.
+    let req = test::TestRequest::post().uri("/login").to_request();
+    let res = test::call_service(&app, req).await;
+    let (fixed, fixed_id) = (session_cookie(&res), session_id(&session(), &res).unwrap());
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .cookie(fixed)
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    let (cookie, id) = (session_cookie(&res), session_id(&session(), &res).unwrap());
+    assert_ne!(fixed_id, id);
+
+    let req = test::TestRequest::post()
+        .uri("/password")
+        .cookie(cookie)
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    let rotated = session_cookie(&res);
+    assert_ne!(id, session_id(&session(), &res).unwrap());
+    let req = test::TestRequest::get()
+        .uri("/")
+        .cookie(rotated.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "1");
+
+    // The fingerprint is still checked.
+    let req = test::TestRequest::get()
+        .uri("/")
+        .cookie(rotated)
+        .insert_header((USER_AGENT, "other"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 302);
+}
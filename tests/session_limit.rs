@@ -0,0 +1,182 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::cookie::Cookie;
+use actix_web::dev::Transform;
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App, Error, HttpRequest, HttpResponse};
+use common::{session_cookie, User};
+use loginmanager::{
+    CookieSession, LoginManager, MemoryRegistry, SessionLimit, SessionRegistry, UserWrap,
+};
+use std::time::Duration;
+
+async fn login(req: HttpRequest) -> Result<String, Error> {
+    let evicted = loginmanager::try_login(&UserWrap::from(User { id: 1 }), &req)?;
+    Ok(evicted.to_string())
+}
+
+async fn logout(req: HttpRequest, user: UserWrap<User>) -> HttpResponse {
+    loginmanager::logout(&user, &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn index(UserWrap(user): UserWrap<User>) -> String {
+    user.id.to_string()
+}
+
+fn manager(
+    registry: &MemoryRegistry,
+    limit: usize,
+    policy: SessionLimit,
+) -> LoginManager<CookieSession> {
+    LoginManager::new(CookieSession::new(&[0; 32]).secure(false))
+        .redirect(false)
+        .session_registry(registry.clone())
+        .max_sessions_per_user(limit, policy)
+}
+
+fn post(uri: &str, cookie: Option<&Cookie<'static>>) -> test::TestRequest {
+    let req = test::TestRequest::post().uri(uri);
+    match cookie {
+        Some(cookie) => req.cookie(cookie.clone()),
+        None => req,
+    }
+}
+
+#[actix_web::test]
+async fn test_evict_oldest() {
+    let registry = MemoryRegistry::new();
+    let app = test::init_service(
+        App::new()
+            .wrap(manager(&registry, 2, SessionLimit::EvictOldest))
+            .route("/login", web::post().to(login))
+            .route("/logout", web::post().to(logout))
+            .route("/", web::post().to(index)),
+    )
+    .await;
+    let mut cookies = Vec::new();
+    for evicted in ["0", "0", "1"] {
+        let res = test::call_service(&app, post("/login", None).to_request()).await;
+        cookies.push(session_cookie(&res));
+        assert_eq!(test::read_body(res).await, evicted);
+    }
+    assert_eq!(registry.sessions("default", "1").len(), 2);
+
+    let res = test::call_service(&app, post("/", Some(&cookies[0])).to_request()).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(session_cookie(&res).value(), "");
+    for cookie in &cookies[1..] {
+        let res = test::call_service(&app, post("/", Some(cookie)).to_request()).await;
+        assert_eq!(test::read_body(res).await, "1");
+    }
+
+    test::call_service(&app, post("/logout", Some(&cookies[2])).to_request()).await;
+    assert_eq!(registry.sessions("default", "1").len(), 1);
+}
+
+#[actix_web::test]
+async fn test_reject() {
+    let registry = MemoryRegistry::new();
+    let app = test::init_service(
+        App::new()
+            .wrap(manager(&registry, 1, SessionLimit::Reject))
+            .route("/login", web::post().to(login)),
+    )
+    .await;
+    let cookie = session_cookie(&test::call_service(&app, post("/login", None).to_request()).await);
+    let res = test::call_service(&app, post("/login", None).to_request()).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // The login again in the same session replaces it.
+    let res = test::call_service(&app, post("/login", Some(&cookie)).to_request()).await;
+    assert_eq!(test::read_body(res).await, "0");
+    assert_eq!(registry.sessions("default", "1").len(), 1);
+
+    let manager = LoginManager::new(CookieSession::new(&[0; 32]))
+        .max_sessions_per_user(1, SessionLimit::Reject);
+    assert!(manager.new_transform(test::ok_service()).await.is_err());
+}
+
+async fn logout_excluded(req: HttpRequest) -> HttpResponse {
+    loginmanager::logout(&UserWrap::from(User { id: 1 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+#[actix_web::test]
+async fn test_excluded_login() {
+    let registry = MemoryRegistry::new();
+    let app = test::init_service(
+        App::new()
+            .wrap(manager(&registry, 1, SessionLimit::Reject).exclude(["/login", "/logout"]))
+            .route("/login", web::post().to(login))
+            .route("/logout", web::post().to(logout_excluded)),
+    )
+    .await;
+    let cookie = session_cookie(&test::call_service(&app, post("/login", None).to_request()).await);
+    let res = test::call_service(&app, post("/login", Some(&cookie)).to_request()).await;
+    let cookie = session_cookie(&res);
+    assert_eq!(test::read_body(res).await, "0");
+    assert_eq!(registry.sessions("default", "1").len(), 1);
+
+    test::call_service(&app, post("/logout", Some(&cookie)).to_request()).await;
+    assert!(registry.sessions("default", "1").is_empty());
+}
+
+#[actix_web::test]
+async fn test_idle_timeout() {
+    let registry = MemoryRegistry::new().idle_timeout(Duration::from_millis(100));
+    let app = test::init_service(
+        App::new()
+            .wrap(manager(&registry, 1, SessionLimit::Reject))
+            .route("/login", web::post().to(login))
+            .route("/", web::post().to(index)),
+    )
+    .await;
+    let cookie = session_cookie(&test::call_service(&app, post("/login", None).to_request()).await);
+    actix_web::rt::time::sleep(Duration::from_millis(150)).await;
+
+    // The forgotten session does not lock the user out.
+    let res = test::call_service(&app, post("/login", None).to_request()).await;
+    assert_eq!(test::read_body(res).await, "0");
+    assert_eq!(registry.sessions("default", "1").len(), 1);
+    let res = test::call_service(&app, post("/", Some(&cookie)).to_request()).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+async fn login_additional(req: HttpRequest, id: web::Path<i32>) -> Result<String, Error> {
+    let evicted = loginmanager::try_login_additional(&UserWrap::from(User { id: *id }), &req)?;
+    Ok(evicted.to_string())
+}
+
+async fn switch(req: HttpRequest, index: web::Path<usize>) -> HttpResponse {
+    loginmanager::switch_account::<User>(&req, *index);
+    HttpResponse::Ok().finish()
+}
+
+#[actix_web::test]
+async fn test_additional_accounts() {
+    let registry = MemoryRegistry::new();
+    let app = test::init_service(
+        App::new()
+            .wrap(manager(&registry, 1, SessionLimit::Reject))
+            .route("/login", web::post().to(login))
+            .route("/login_additional/{id}", web::post().to(login_additional))
+            .route("/switch/{index}", web::post().to(switch)),
+    )
+    .await;
+    let cookie = session_cookie(&test::call_service(&app, post("/login", None).to_request()).await);
+    let res = post("/login_additional/2", Some(&cookie)).to_request();
+    let cookie = session_cookie(&test::call_service(&app, res).await);
+    let res = test::call_service(&app, post("/switch/0", Some(&cookie)).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // All the accounts of the session count.
+    for user in ["1", "2"] {
+        assert_eq!(registry.sessions("default", user).len(), 1);
+    }
+    let res = test::call_service(&app, post("/login", None).to_request()).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = test::call_service(&app, post("/login_additional/2", None).to_request()).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::cookie::Cookie;
+use actix_web::http::header::USER_AGENT;
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use common::{session_cookie, User};
+use loginmanager::{CookieSession, LoginManager, MemoryRegistry, UserAgent, UserWrap};
+use serde_json::Value;
+
+const FIREFOX: &str =
+    "Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0";
+const SAFARI: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Mobile/15E148 Safari/604.1";
+const CHROME: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+
+async fn login(req: HttpRequest) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(User { id: 1 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn impersonate(req: HttpRequest, _: UserWrap<User>) -> HttpResponse {
+    loginmanager::impersonate(&req, &UserWrap::from(User { id: 2 }));
+    HttpResponse::Ok().finish()
+}
+
+async fn login_additional(req: HttpRequest, _: UserWrap<User>) -> HttpResponse {
+    loginmanager::login_additional(&UserWrap::from(User { id: 2 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn switch(req: HttpRequest) -> HttpResponse {
+    loginmanager::switch_account::<User>(&req, 0);
+    HttpResponse::Ok().finish()
+}
+
+async fn sessions(req: HttpRequest, _: UserWrap<User>) -> HttpResponse {
+    HttpResponse::Ok().json(loginmanager::list_sessions::<User>(&req))
+}
+
+async fn revoke(req: HttpRequest, _: UserWrap<User>, handle: web::Path<String>) -> String {
+    loginmanager::revoke_session::<User>(&req, &handle).to_string()
+}
+
+async fn revoke_others(req: HttpRequest, _: UserWrap<User>) -> String {
+    loginmanager::revoke_other_sessions::<User>(&req).to_string()
+}
+
+fn request(uri: &str, cookie: Option<&Cookie<'static>>, user_agent: &str) -> test::TestRequest {
+    let req = test::TestRequest::post()
+        .uri(uri)
+        .peer_addr("10.0.0.1:4000".parse().unwrap())
+        .insert_header((USER_AGENT, user_agent));
+    match cookie {
+        Some(cookie) => req.cookie(cookie.clone()),
+        None => req,
+    }
+}
+
+#[actix_web::test]
+async fn test_sessions() {
+    let registry = MemoryRegistry::new();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                LoginManager::new(CookieSession::new(&[0; 32]).secure(false))
+                    .redirect(false)
+                    .session_registry(registry.clone()),
+            )
+            .route("/login", web::post().to(login))
+            .route("/sessions", web::post().to(sessions))
+            .route("/revoke/{handle}", web::post().to(revoke))
+            .route("/revoke_others", web::post().to(revoke_others)),
+    )
+    .await;
+    let mut cookies = Vec::new();
+    for user_agent in [FIREFOX, SAFARI, CHROME] {
+        let req = request("/login", None, user_agent).to_request();
+        cookies.push(session_cookie(&test::call_service(&app, req).await));
+    }
+
+    let req = request("/sessions", Some(&cookies[0]), FIREFOX).to_request();
+    let list: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(list.len(), 3);
+    assert_eq!(list[0]["current"], true);
+    assert_eq!(list[1]["current"], false);
+    assert_eq!(list[0]["ip"], "10.0.0.1");
+    assert_eq!(list[0]["user_agent"]["browser"], "Firefox 121");
+    assert_eq!(list[1]["user_agent"]["os"], "iOS");
+    assert!(
+        list[0]["last_seen"]["secs_since_epoch"].as_u64()
+            >= list[0]["created"]["secs_since_epoch"].as_u64()
+    );
+
+    // Revoke the Safari one.
+    let handle = list[1]["handle"].as_str().unwrap();
+    let req = request(&format!("/revoke/{}", handle), Some(&cookies[0]), FIREFOX).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let req = request(&format!("/revoke/{}", handle), Some(&cookies[0]), FIREFOX).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "false");
+    let req = request("/sessions", Some(&cookies[1]), SAFARI).to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let req = request("/revoke_others", Some(&cookies[0]), FIREFOX).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "1");
+    let req = request("/sessions", Some(&cookies[2]), CHROME).to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Revoke the current one logs out.
+    let req = request("/sessions", Some(&cookies[0]), FIREFOX).to_request();
+    let list: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(list.len(), 1);
+    let handle = list[0]["handle"].as_str().unwrap();
+    let req = request(&format!("/revoke/{}", handle), Some(&cookies[0]), FIREFOX).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(session_cookie(&res).value(), "");
+}
+
+#[actix_web::test]
+async fn test_real_user() {
+    let registry = MemoryRegistry::new();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                LoginManager::new(CookieSession::new(&[0; 32]).secure(false))
+                    .redirect(false)
+                    .session_registry(registry.clone()),
+            )
+            .route("/login", web::post().to(login))
+            .route("/impersonate", web::post().to(impersonate))
+            .route("/login_additional", web::post().to(login_additional))
+            .route("/switch", web::post().to(switch))
+            .route("/sessions", web::post().to(sessions))
+            .route("/revoke_others", web::post().to(revoke_others)),
+    )
+    .await;
+    let call = |uri: &str, cookie: &Cookie<'static>| {
+        let req = request(uri, Some(cookie), FIREFOX).to_request();
+        test::call_service(&app, req)
+    };
+    let mut cookies = Vec::new();
+    for _ in 0..3 {
+        let req = request("/login", None, FIREFOX).to_request();
+        cookies.push(session_cookie(&test::call_service(&app, req).await));
+    }
+
+    // The session of the accounts 1 and 2 is of both.
+    let cookie = session_cookie(&call("/login_additional", &cookies[1]).await);
+    let list: Vec<Value> = test::read_body_json(call("/sessions", &cookie).await).await;
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0]["current"], true);
+    let cookie = session_cookie(&call("/switch", &cookie).await);
+    let list: Vec<Value> = test::read_body_json(call("/sessions", &cookie).await).await;
+    assert_eq!(list.len(), 3);
+    assert_eq!(list[1]["current"], true);
+
+    // The impersonated user 2 is not the owner.
+
+    let cookie = session_cookie(&call("/impersonate", &cookies[0]).await);
+    let list: Vec<Value> = test::read_body_json(call("/sessions", &cookie).await).await;
+    assert_eq!(list.len(), 3);
+    assert_eq!(list[0]["current"], true);
+    let res = call("/revoke_others", &cookie).await;
+    assert_eq!(test::read_body(res).await, "2");
+    let res = call("/sessions", &cookies[2]).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_user_agent() {
+    let parse = |user_agent| {
+        let UserAgent { browser, os } = UserAgent::parse(user_agent);
+        (browser.unwrap_or_default(), os.unwrap_or_default())
+    };
+    assert_eq!(
+        parse(FIREFOX),
+        ("Firefox 121".to_owned(), "Linux".to_owned())
+    );
+    assert_eq!(parse(SAFARI), ("Safari 17".to_owned(), "iOS".to_owned()));
+    assert_eq!(
+        parse(CHROME),
+        ("Chrome 120".to_owned(), "Windows".to_owned())
+    );
+    assert_eq!(parse("curl/8.4.0"), (String::new(), String::new()));
+}
@@ -0,0 +1,83 @@
+use actix_loginmanager as loginmanager;
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use futures::future::{self, Ready};
+use loginmanager::{
+    login_required, CookieSession, LoginManager, SharedUser, SyncUserWrap, UserCache, UserMinix,
+    UserWrap,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone)]
+struct User {
+    id: i32,
+}
+
+impl UserMinix for User {
+    type Future = Ready<Option<Self>>;
+    type Key = i32;
+
+    fn get_user(id: &i32, _: &HttpRequest) -> Self::Future {
+        future::ready(Some(User { id: *id }))
+    }
+
+    fn get_id(&self) -> &i32 {
+        &self.id
+    }
+
+    fn to_shared(&self) -> Option<SharedUser> {
+        Some(Arc::new(self.clone()))
+    }
+
+    fn from_shared(user: &SharedUser) -> Option<Self> {
+        user.downcast_ref::<Self>().cloned()
+    }
+}
+
+async fn login(req: HttpRequest) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(User { id: 1 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn block(SyncUserWrap(user): SyncUserWrap<User>) -> actix_web::Result<String> {
+    Ok(web::block(move || format!("block {}", user.id)).await?)
+}
+
+#[login_required(User, sync)]
+async fn thread() -> impl actix_web::Responder {
+    std::thread::spawn(move || format!("thread {}", user.id))
+        .join()
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_sync_user() {
+    let cache = UserCache::new(Duration::from_secs(60), 10);
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                LoginManager::new(CookieSession::new(&[0; 32]).secure(false))
+                    .user_cache(cache.clone()),
+            )
+            .route("/login", web::get().to(login))
+            .route("/block", web::get().to(block))
+            .route("/thread", web::get().to(thread)),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/block").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 302);
+
+    let req = test::TestRequest::get().uri("/login").to_request();
+    let res = test::call_service(&app, req).await;
+    let cookie = res.response().cookies().next().unwrap().into_owned();
+
+    let req = test::TestRequest::get()
+        .uri("/block")
+        .cookie(cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "block 1");
+    assert_eq!(cache.len(), 1);
+    let req = test::TestRequest::get().uri("/thread").cookie(cookie).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "thread 1");
+}
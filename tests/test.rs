@@ -1,7 +1,14 @@
 use std::pin::Pin;
-use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer};
+use actix_web::body::MessageBody;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header::LOCATION, StatusCode};
+use actix_web::{get, test, web, App, HttpRequest, HttpResponse};
 use actix_loginmanager as loginmanager;
-use loginmanager::{CookieSession, LoginManager, UserMinix, UserWrap};
+use loginmanager::{
+    CookieSession, CurrentUser, LoginManager, PathPattern, RequireLogin, UnauthorizedHandler,
+    UserMinix, UserWrap,
+};
 
 use futures::Future;
 use loginmanager_codegen::login_required;
@@ -33,16 +40,8 @@ impl UserMinix for User {
     type Future = Pin<Box<dyn Future<Output = Option<Self>>>>;
     type Key = i32;
     fn get_user(i: &Self::Key, _: &HttpRequest) -> Self::Future {
-        // let req = req.clone();
-        let i = i.clone();
-        Box::pin(async move {
-            for id in 0..USERS.len() {
-                if USERS[id].id == i {
-                    return Some(USERS[id].clone());
-                }
-            }
-            None
-        })
+        let i = *i;
+        Box::pin(async move { USERS.iter().find(|user| user.id == i).cloned() })
     }
 
     fn get_id(&self) -> &Self::Key {
@@ -81,26 +80,242 @@ async fn index(UserWrap(user): UserWrap<User>) -> impl actix_web::Responder {
     ))
 }
 
-#[actix_web::main]
-#[test]
-async fn main() {
-    HttpServer::new(|| {
+async fn next(req: HttpRequest) -> impl actix_web::Responder {
+    loginmanager::safe_next(&req)
+}
+
+async fn home(user: CurrentUser<User>) -> impl actix_web::Responder {
+    match user {
+        CurrentUser::Authenticated(UserWrap(user)) => format!("home {}", user.name),
+        CurrentUser::Anonymous(_) => "home guest".to_owned(),
+    }
+}
+
+fn app() -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .wrap(
+            LoginManager::new(CookieSession::new(&[0; 32]).secure(false))
+                .exclude(vec!["/static", "/login"]),
+        )
+        .service(
+            web::scope("path")
+                .service(index)
+        )
+        .service(
+            web::scope("api")
+                .app_data(UnauthorizedHandler::Status)
+                .service(index)
+        )
+        .service(
+            web::scope("admin")
+                .wrap(RequireLogin::<User>::new())
+                .route("/name", web::get().to(need_login))
+                .route("/public", web::get().to(|| async { "public" })),
+        )
+        .service(
+            web::resource("/profile")
+                .wrap(RequireLogin::<User>::new())
+                .route(web::get().to(need_login)),
+        )
+        .service(index)
+        .route("/hello", web::get().to(hello))
+        .route("/static/hello", web::get().to(hello))
+        .route("/home", web::get().to(home))
+        .route("/next", web::get().to(next))
+        .route("/login", web::get().to(auto_login))
+        .route("/logout", web::get().to(logout))
+}
+
+fn session_cookie<B>(res: &ServiceResponse<B>) -> Cookie<'static> {
+    res.response()
+        .cookies()
+        .find(|cookie| cookie.name() == "_session")
+        .expect("no session cookie")
+        .into_owned()
+}
+
+#[actix_web::test]
+async fn test_login_required() {
+    let app = test::init_service(app()).await;
+
+    let req = test::TestRequest::get().uri("/hello?a=1&b=2").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "/login?next=%2Fhello%3Fa%3D1%26b%3D2");
+
+    let req = test::TestRequest::get().uri("/login").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookie = session_cookie(&res);
+
+    let req = test::TestRequest::get().uri("/hello").cookie(cookie.clone()).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "hello Tom");
+
+    let req = test::TestRequest::get().uri("/path/").cookie(cookie.clone()).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "Hello:\"Tom\" is_authenticated:true");
+
+    let req = test::TestRequest::get().uri("/logout").cookie(cookie).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookie = session_cookie(&res);
+
+    let req = test::TestRequest::get().uri("/").cookie(cookie).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+}
+
+#[actix_web::test]
+async fn test_current_user() {
+    let app = test::init_service(app()).await;
+
+    let req = test::TestRequest::get().uri("/home").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(test::read_body(res).await, "home guest");
+
+    let req = test::TestRequest::get().uri("/login").to_request();
+    let cookie = session_cookie(&test::call_service(&app, req).await);
+
+    let req = test::TestRequest::get().uri("/home").cookie(cookie).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "home Tom");
+}
+
+#[actix_web::test]
+async fn test_unauthorized_handler() {
+    let app = test::init_service(app()).await;
+
+    let req = test::TestRequest::get()
+        .uri("/hello")
+        .insert_header(("Accept", "application/json"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers().get("Content-Type").unwrap(), "application/problem+json");
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["status"], 401);
+    assert_eq!(body["reason"], "unauthenticated");
+    assert_eq!(body["instance"], "/hello");
+
+    let req = test::TestRequest::get()
+        .uri("/hello")
+        .insert_header(("Accept", "text/html,application/json;q=0.9"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+
+    let req = test::TestRequest::get()
+        .uri("/hello")
+        .insert_header(("HX-Request", "true"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers().get("HX-Redirect").unwrap(), "/login?next=%2Fhello");
+
+    let req = test::TestRequest::get().uri("/api/").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert!(res.headers().get(LOCATION).is_none());
+}
+
+#[actix_web::test]
+async fn test_safe_next() {
+    let app = test::init_service(app()).await;
+    for (next, expected) in [
+        ("%2Fhello%3Fa%3D1%26b%3D2", "/hello?a=1&b=2"),
+        ("%2F%E4%BD%A0%E5%A5%BD", "/你好"),
+        ("https%3A%2F%2Fevil.com%2F", "/"),
+        ("%2F%2Fevil.com", "/"),
+        ("%2F%5Cevil.com", "/"),
+        ("%2F%09%2Fevil.com", "/"),
+        ("hello", "/"),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/next?next={}", next))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, expected);
+    }
+    let req = test::TestRequest::get().uri("/next").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "/");
+}
+
+#[actix_web::test]
+async fn test_exclude() {
+    let app = test::init_service(app()).await;
+
+    let req = test::TestRequest::get().uri("/static/hello").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert!(res.headers().get(LOCATION).is_none());
+
+    let req = test::TestRequest::default()
+        .method(actix_web::http::Method::OPTIONS)
+        .uri("/hello")
+        .insert_header(("Access-Control-Request-Method", "GET"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert!(res.headers().get(LOCATION).is_none());
+
+    let pattern = PathPattern::prefix("/static");
+    assert!(pattern.is_match("/static"));
+    assert!(pattern.is_match("/static/a.css"));
+    assert!(!pattern.is_match("/statics"));
+    let pattern = PathPattern::glob("/assets/*.css");
+    assert!(pattern.is_match("/assets/a.css"));
+    assert!(!pattern.is_match("/assets/a/b.css"));
+    assert!(PathPattern::glob("/assets/**.css").is_match("/assets/a/b.css"));
+    let pattern = PathPattern::glob("/**/*.ico");
+    assert!(pattern.is_match("/favicon.ico"));
+    assert!(pattern.is_match("/a/b/favicon.ico"));
+    assert!(!pattern.is_match("favicon.ico"));
+    assert!(PathPattern::regex("^/health(z)?$").unwrap().is_match("/healthz"));
+}
+
+async fn need_login(UserWrap(user): UserWrap<User>) -> impl actix_web::Responder {
+    user.name
+}
+
+#[actix_web::test]
+async fn test_login_view_loop() {
+    let app = test::init_service(
         App::new()
-            .wrap(LoginManager::new(
-                CookieSession::new(&[0; 32]).secure(false),
-            ))
-            .service(
-                web::scope("path")
-                    .service(index)
+            .wrap(
+                // The login view needs not be excluded, the redirect skips it.
+                LoginManager::new(CookieSession::new(&[0; 32]).secure(false))
+                    .exclude(["/static"]),
             )
-            .service(index)
-            .route("/hello", web::get().to(hello))
-            .route("/login", web::get().to(auto_login))
-            .route("/logout", web::get().to(logout))
-    })
-    .bind("0.0.0.0:7081")
-    .unwrap()
-    .run()
-    .await
-    .unwrap();
+            .route("/login", web::get().to(need_login)),
+    )
+    .await;
+    let req = test::TestRequest::get().uri("/login").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let manager = LoginManager::new(CookieSession::new(&[0; 32])).login_view("login".to_owned());
+    assert!(manager.new_transform(test::ok_service()).await.is_err());
+}
+
+#[actix_web::test]
+async fn test_require_login() {
+    let app = test::init_service(app()).await;
+
+    for uri in ["/admin/name", "/admin/public", "/profile"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+    }
+
+    let req = test::TestRequest::get().uri("/login").to_request();
+    let cookie = session_cookie(&test::call_service(&app, req).await);
+    for (uri, body) in [("/admin/name", "Tom"), ("/admin/public", "public"), ("/profile", "Tom")] {
+        let req = test::TestRequest::get().uri(uri).cookie(cookie.clone()).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, body);
+    }
 }
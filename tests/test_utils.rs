@@ -0,0 +1,61 @@
+mod common;
+
+use actix_loginmanager as loginmanager;
+use actix_web::{test, web, App, FromRequest, HttpRequest, HttpResponse};
+use common::User;
+use loginmanager::test::{assert_logged_in, assert_logged_out, inject_user, TestRequestExt};
+use loginmanager::{CookieSession, LoginManager, UserWrap};
+
+async fn login(req: HttpRequest) -> HttpResponse {
+    loginmanager::login(&UserWrap::from(User { id: 1 }), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn logout(req: HttpRequest, UserWrap(user): UserWrap<User>) -> HttpResponse {
+    loginmanager::logout(&UserWrap(user), &req);
+    HttpResponse::Ok().finish()
+}
+
+async fn index(UserWrap(user): UserWrap<User>) -> String {
+    user.id.to_string()
+}
+
+fn session() -> CookieSession {
+    CookieSession::new(&[0; 32]).secure(false)
+}
+
+#[actix_web::test]
+async fn test_test_utils() {
+    let app = test::init_service(
+        App::new()
+            .wrap(LoginManager::new(session()))
+            .route("/", web::get().to(index))
+            .route("/login", web::get().to(login))
+            .route("/logout", web::get().to(logout)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/")
+        .login_as(&session(), &User { id: 2 })
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "2");
+
+    let req = test::TestRequest::get().uri("/login").to_request();
+    assert_logged_in(
+        &session(),
+        &test::call_service(&app, req).await,
+        &User { id: 1 },
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/logout")
+        .login_as(&session(), &User { id: 2 })
+        .to_request();
+    assert_logged_out(&session(), &test::call_service(&app, req).await);
+
+    let req = test::TestRequest::default().to_http_request();
+    inject_user(&req, User { id: 4 });
+    let UserWrap(user) = UserWrap::<User>::extract(&req).await.unwrap();
+    assert_eq!(user.id, 4);
+}
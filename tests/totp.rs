@@ -0,0 +1,118 @@
+use actix_loginmanager::totp::{self, Totp, TotpUser};
+use actix_loginmanager::UserMinix;
+use actix_web::HttpRequest;
+use futures::future::{self, Ready};
+
+// The secret of the test vectors of RFC 6238, "12345678901234567890".
+const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+struct User {
+    id: i32,
+    secret: String,
+    last_step: Option<u64>,
+}
+
+impl UserMinix for User {
+    type Future = Ready<Option<Self>>;
+    type Key = i32;
+
+    fn get_user(_: &i32, _: &HttpRequest) -> Self::Future {
+        future::ready(None)
+    }
+
+    fn get_id(&self) -> &i32 {
+        &self.id
+    }
+}
+
+impl TotpUser for User {
+    fn totp_secret(&self) -> Option<&str> {
+        Some(&self.secret)
+    }
+
+    fn totp_last_step(&self) -> Option<u64> {
+        self.last_step
+    }
+}
+
+#[test]
+fn test_totp() {
+    let totp = Totp::new().digits(8);
+    assert_eq!(totp.code(SECRET, totp.step_at(59)).unwrap(), "94287082");
+    assert_eq!(
+        totp.code(SECRET, totp.step_at(1111111109)).unwrap(),
+        "07081804"
+    );
+    assert_eq!(
+        totp.code(SECRET, totp.step_at(1234567890)).unwrap(),
+        "89005924"
+    );
+    assert_eq!(
+        totp.code(SECRET, totp.step_at(20000000000)).unwrap(),
+        "65353130"
+    );
+
+    let step = totp.step_at(1111111109);
+    assert_eq!(
+        totp.verify_at(SECRET, "07081804", None, 1111111109),
+        Some(step)
+    );
+    assert_eq!(
+        totp.verify_at(SECRET, "07081804", None, 1111111109 + 30),
+        Some(step)
+    );
+    assert_eq!(
+        totp.verify_at(SECRET, "07081804", None, 1111111109 + 60),
+        None
+    );
+    assert_eq!(
+        totp.clone()
+            .skew(2)
+            .verify_at(SECRET, "07081804", None, 1111111169),
+        Some(step)
+    );
+    assert_eq!(
+        totp.verify_at(SECRET, "07081804", Some(step), 1111111109),
+        None
+    );
+    assert_eq!(totp.verify_at(SECRET, "00000000", None, 1111111109), None);
+    let totp_max = Totp::new().step(1).skew(u64::MAX);
+    assert_eq!(totp_max.verify_at(SECRET, "000000", None, u64::MAX), None);
+    assert_eq!(
+        Totp::new()
+            .digits(20)
+            .code(SECRET, totp.step_at(59))
+            .unwrap(),
+        "94287082"
+    );
+    assert_eq!(Totp::new().digits(0).code(SECRET, 1).unwrap().len(), 6);
+
+    let secret = totp::generate_secret();
+    assert_eq!(secret.len(), 32);
+    let totp = Totp::new();
+    let mut user = User {
+        id: 1,
+        secret,
+        last_step: None,
+    };
+    let code = totp.code(&user.secret, totp.step_at(now())).unwrap();
+    user.last_step = totp.verify_user(&user, &code);
+    assert!(user.last_step.is_some());
+    assert_eq!(totp.verify_user(&user, &code), None);
+
+    assert_eq!(
+        totp.provisioning_uri(SECRET, "Example Inc", "tom@example.com"),
+        format!(
+            "otpauth://totp/Example%20Inc:tom%40example.com?secret={}\
+             &issuer=Example%20Inc&algorithm=SHA1&digits=6&period=30",
+            SECRET
+        )
+    );
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}